@@ -0,0 +1,88 @@
+//! The conventions for the control signals passed between nodes.
+//!
+//! Pitch is sent between nodes as a control voltage in volts per octave: a change
+//! of `1.0` is one octave, and `0.0` is [CV_REFERENCE_HZ], which is middle C. Every
+//! node that outputs a pitch, such as the [crate::nodes::sequencer::SequencerNode],
+//! and every node that follows one, such as the
+//! [crate::nodes::wavetable::WaveTableNode], uses volts, so a modulation source like
+//! an [crate::nodes::lfo::LfoNode] can be added straight onto a pitch.
+//!
+//! There is no voltage for "no note", so whether a note is playing is sent on a
+//! separate gate, and pitch outputs hold their last value between notes. The nodes
+//! in [crate::nodes::convert] change to and from hertz for anything that works
+//! with a frequency instead.
+
+use crate::pitch::{DEFAULT_A4_HZ, midi_to_hz};
+
+/// The frequency of `0.0` volts, which is middle C
+pub const CV_REFERENCE_HZ: f32 = 261.625_57;
+
+/// Converts a frequency to volts per octave. The frequency must be above 0 Hz.
+#[inline]
+pub fn frequency_to_voltage(frequency: f32) -> f32 {
+    (frequency / CV_REFERENCE_HZ).log2()
+}
+
+/// Converts volts per octave to a frequency, which is the exact inverse of
+/// [frequency_to_voltage]
+#[inline]
+pub fn voltage_to_frequency(voltage: f32) -> f32 {
+    CV_REFERENCE_HZ * voltage.exp2()
+}
+
+/// The voltage of a MIDI note, where note 60 is `0.0` and each semitone is a
+/// twelfth of a volt
+#[inline]
+pub fn midi_to_voltage(note: f32) -> f32 {
+    frequency_to_voltage(midi_to_hz(note, DEFAULT_A4_HZ))
+}
+
+/// Turns the frequencies of notes into volts per octave for a pitch output,
+/// holding the last voltage while the frequency is at or below 0 Hz, such as
+/// during a rest
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeldPitch {
+    frequency: f32,
+    voltage: f32,
+}
+
+impl HeldPitch {
+    /// Starts out holding `voltage` until the first note
+    pub fn new(voltage: f32) -> Self {
+        Self {
+            frequency: 0.0,
+            voltage,
+        }
+    }
+
+    /// Returns the voltage for `frequency`, or the last voltage when it is not
+    /// above 0 Hz
+    #[inline]
+    pub fn voltage(&mut self, frequency: f32) -> f32 {
+        if frequency > 0.0 && frequency != self.frequency {
+            self.frequency = frequency;
+            self.voltage = frequency_to_voltage(frequency);
+        }
+        self.voltage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_volts_per_octave() {
+        assert!((CV_REFERENCE_HZ - midi_to_hz(60.0, DEFAULT_A4_HZ)).abs() < 1e-3);
+        assert_eq!(frequency_to_voltage(CV_REFERENCE_HZ), 0.0);
+        assert_eq!(frequency_to_voltage(CV_REFERENCE_HZ * 2.0), 1.0);
+        assert_eq!(frequency_to_voltage(CV_REFERENCE_HZ / 4.0), -2.0);
+        assert!((midi_to_voltage(69.0) - 0.75).abs() < 1e-6);
+
+        // the same resolution at every octave, and back again
+        for frequency in [20.0, 27.5, 55.0, 440.0, 1_000.0, 20_000.0] {
+            let round_trip = voltage_to_frequency(frequency_to_voltage(frequency));
+            assert!((round_trip / frequency - 1.0).abs() < 1e-6, "{frequency}");
+        }
+    }
+}
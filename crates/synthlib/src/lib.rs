@@ -1,2 +1,8 @@
 pub mod audio;
+pub mod cv;
 pub mod nodes;
+pub mod pitch;
+pub mod rng;
+pub mod scale;
+pub mod tempo;
+pub mod tuning;
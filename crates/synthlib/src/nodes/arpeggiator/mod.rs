@@ -0,0 +1,145 @@
+use firewheel::{
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    node::{AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext},
+};
+
+pub mod processor;
+
+use processor::ArpeggiatorProcessor;
+
+use crate::{nodes::sequencer::NUM_OUTPUTS, tempo::NoteValue, tuning::Tuning};
+
+/// The most notes an [ArpeggiatorNode] can hold at once
+pub const MAX_NOTES: usize = 32;
+/// The most octaves an [ArpeggiatorNode] can play over
+pub const MAX_OCTAVES: u32 = 4;
+/// The most chord inputs an [ArpeggiatorNode] can have
+pub const MAX_CHORD_INPUTS: u32 = 8;
+
+/// Plays the notes that are held down one at a time, in time with the tempo.
+///
+/// Notes are held by sending [ArpeggiatorEvent]s, or through the chord inputs
+/// (see [ArpeggiatorConfig::chord_inputs]), which each hold a note at their pitch
+/// while their gate is high.
+///
+/// The outputs are the same as a [crate::nodes::sequencer::SequencerNode], so
+/// the arpeggiator can take its place in front of an oscillator and envelope. The
+/// accent output is always low.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct ArpeggiatorNode {
+    /// The tempo in beats per minute, where a beat is a quarter note
+    pub bpm: f32,
+    /// The length of each note of the arpeggio
+    pub rate: NoteValue,
+    pub order: ArpOrder,
+    /// The number of octaves the held notes are played over, from 1 to
+    /// [MAX_OCTAVES]
+    pub octaves: u32,
+    /// How much of each note the gate is held for, in percent
+    pub gate_percent: f32,
+    /// Keeps playing the notes after they are released. Holding new notes after
+    /// every note has been released replaces them.
+    pub latch: bool,
+}
+
+impl Default for ArpeggiatorNode {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            rate: NoteValue::SIXTEENTH,
+            order: ArpOrder::default(),
+            octaves: 1,
+            gate_percent: 50.0,
+            latch: false,
+        }
+    }
+}
+
+/// The order an [ArpeggiatorNode] plays the held notes in
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArpOrder {
+    /// From the lowest note to the highest
+    #[default]
+    Up,
+    /// From the highest note to the lowest
+    Down,
+    /// Up and then back down, without repeating the highest and lowest notes
+    UpDown,
+    /// A random note each time
+    Random,
+    /// In the order the notes were pressed
+    AsPlayed,
+}
+
+/// Note events that can be sent to an [ArpeggiatorNode] with
+/// `NodeEventType::custom`.
+///
+/// Each note is identified by a `key` chosen by the caller (for example a MIDI
+/// note number) so that the matching note off can find it. The last
+/// [MAX_CHORD_INPUTS] keys are used by the chord inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpeggiatorEvent {
+    NoteOn {
+        key: u32,
+        frequency: f32,
+        velocity: f32,
+    },
+    /// Holds a MIDI note at its frequency in [ArpeggiatorConfig::tuning], using
+    /// the note number as the key. Notes the tuning doesn't map are ignored.
+    MidiNoteOn {
+        note: u8,
+        velocity: f32,
+    },
+    NoteOff {
+        key: u32,
+    },
+    /// Releases every note, including latched ones
+    AllNotesOff,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArpeggiatorConfig {
+    /// The number of chord inputs, for example from a chord of sequencers, up to
+    /// [MAX_CHORD_INPUTS]. Each one is a pair of inputs: a pitch in volts per
+    /// octave (see [crate::cv]) followed by a gate, so chord input `i` uses inputs
+    /// `2 * i` and `2 * i + 1`.
+    pub chord_inputs: u32,
+    /// The seed for [ArpOrder::Random]
+    pub seed: u32,
+    /// The tuning of [ArpeggiatorEvent::MidiNoteOn], or equal temperament from
+    /// A4 at 440 Hz when `None`
+    pub tuning: Option<Tuning>,
+}
+
+impl Default for ArpeggiatorConfig {
+    fn default() -> Self {
+        Self {
+            chord_inputs: 0,
+            seed: 1,
+            tuning: None,
+        }
+    }
+}
+
+impl AudioNode for ArpeggiatorNode {
+    type Configuration = ArpeggiatorConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("arpeggiator")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::new(configuration.chord_inputs.min(MAX_CHORD_INPUTS) * 2)
+                    .unwrap(),
+                num_outputs: ChannelCount::new(NUM_OUTPUTS).unwrap(),
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        ArpeggiatorProcessor::new(self, configuration, cx.stream_info.sample_rate.into())
+    }
+}
@@ -0,0 +1,466 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    diff::Patch,
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{AudioNodeProcessor, ProcBuffers, ProcInfo, ProcessStatus},
+};
+
+use super::{
+    ArpOrder, ArpeggiatorConfig, ArpeggiatorEvent, ArpeggiatorNode, ArpeggiatorNodePatch,
+    MAX_CHORD_INPUTS, MAX_NOTES, MAX_OCTAVES,
+};
+use crate::{
+    cv::{HeldPitch, voltage_to_frequency},
+    nodes::sequencer::{
+        ACCENT_OUTPUT, GATE_OUTPUT, PITCH_OUTPUT, TRIGGER_OUTPUT, VELOCITY_OUTPUT,
+        processor::TICKS_PER_FRAME,
+    },
+    rng::Rng,
+    tuning::Tuning,
+};
+
+/// The keys used for the notes held by the chord inputs, which come after every
+/// other key
+const CHORD_INPUT_KEY: u32 = u32::MAX - MAX_CHORD_INPUTS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeldNote {
+    key: u32,
+    frequency: f32,
+    velocity: f32,
+    /// Counts up with every note, for [ArpOrder::AsPlayed]
+    order: u64,
+    /// Whether the note is still held down rather than latched
+    pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct ArpNote {
+    frequency: f32,
+    velocity: f32,
+}
+
+pub struct ArpeggiatorProcessor {
+    params: ArpeggiatorNode,
+    notes: Vec<HeldNote>,
+    /// Incremented for every note on, so notes can be played in the order they
+    /// were pressed
+    note_counter: u64,
+    /// The notes in the order they are played, over every octave
+    pattern: Vec<ArpNote>,
+    /// The index in the pattern of the next note
+    position: usize,
+    rng: Rng,
+    tuning: Tuning,
+    /// The frequency each chord input had in the last block
+    chord: [f32; MAX_CHORD_INPUTS as usize],
+    sample_rate: u32,
+    /// The note being played, or `None` while nothing is held
+    note: Option<ArpNote>,
+    /// The pitch output, which holds the last note between notes
+    pitch: HeldPitch,
+    /// The number of frames of the current note that have been played
+    elapsed: u64,
+    /// The length of the current note in frames
+    step_frames: u64,
+    /// The number of frames at the start of the current note that the gate is held
+    gate_frames: u64,
+    /// The part of a frame, in ticks, that the previous notes ran over by
+    carry: u64,
+}
+
+impl ArpeggiatorProcessor {
+    pub fn new(params: &ArpeggiatorNode, config: &ArpeggiatorConfig, sample_rate: u32) -> Self {
+        let mut processor = Self {
+            params: *params,
+            notes: Vec::with_capacity(MAX_NOTES),
+            note_counter: 0,
+            // up and down through every octave is the longest pattern
+            pattern: Vec::with_capacity(MAX_NOTES * MAX_OCTAVES as usize * 2),
+            position: 0,
+            rng: Rng::new(config.seed),
+            tuning: config.tuning.clone().unwrap_or_default(),
+            chord: [0.0; MAX_CHORD_INPUTS as usize],
+            sample_rate,
+            note: None,
+            pitch: HeldPitch::default(),
+            elapsed: 0,
+            step_frames: 0,
+            gate_frames: 0,
+            carry: 0,
+        };
+        processor.start_step();
+        processor
+    }
+
+    fn note_on(&mut self, key: u32, frequency: f32, velocity: f32) -> Result<(), &'static str> {
+        // new notes replace the latched ones once every key has been let go
+        if self.params.latch && !self.notes.iter().any(|note| note.pressed) {
+            self.notes.clear();
+        }
+
+        if let Some(note) = self.notes.iter_mut().find(|note| note.key == key) {
+            note.frequency = frequency;
+            note.velocity = velocity.clamp(0.0, 1.0);
+            note.pressed = true;
+        } else {
+            if self.notes.len() == self.notes.capacity() {
+                return Err("too many notes held in the arpeggiator");
+            }
+
+            self.note_counter += 1;
+            self.notes.push(HeldNote {
+                key,
+                frequency,
+                velocity: velocity.clamp(0.0, 1.0),
+                order: self.note_counter,
+                pressed: true,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn note_off(&mut self, key: u32) {
+        if self.params.latch {
+            for note in self.notes.iter_mut().filter(|note| note.key == key) {
+                note.pressed = false;
+            }
+        } else {
+            self.notes.retain(|note| note.key != key);
+        }
+    }
+
+    fn handle_event(&mut self, event: &ArpeggiatorEvent) -> Result<(), &'static str> {
+        match *event {
+            ArpeggiatorEvent::NoteOn {
+                key,
+                frequency,
+                velocity,
+            } => self.note_on(key, frequency, velocity)?,
+            ArpeggiatorEvent::MidiNoteOn { note, velocity } => {
+                if let Some(frequency) = self.tuning.frequency(note as f32) {
+                    self.note_on(note as u32, frequency, velocity)?;
+                }
+            }
+            ArpeggiatorEvent::NoteOff { key } => self.note_off(key),
+            ArpeggiatorEvent::AllNotesOff => self.notes.clear(),
+        }
+
+        Ok(())
+    }
+
+    fn set_latch(&mut self, latch: bool) {
+        self.params.latch = latch;
+
+        if !latch {
+            self.notes.retain(|note| note.pressed);
+        }
+    }
+
+    /// Holds or releases the notes of the chord inputs when they change
+    fn update_chord(&mut self, chord: &[f32]) -> Result<(), &'static str> {
+        let mut result = Ok(());
+
+        for (i, frequency) in chord.iter().enumerate() {
+            let key = CHORD_INPUT_KEY + i as u32;
+            let frequency = frequency.max(0.0);
+
+            if frequency != self.chord[i] {
+                if frequency > 0.0 {
+                    result = result.and(self.note_on(key, frequency, 1.0));
+                } else {
+                    self.note_off(key);
+                }
+                self.chord[i] = frequency;
+            }
+        }
+
+        result
+    }
+
+    /// Works out the order the held notes are played in
+    fn build_pattern(&mut self) {
+        match self.params.order {
+            ArpOrder::AsPlayed => self.notes.sort_unstable_by_key(|note| note.order),
+            _ => self
+                .notes
+                .sort_unstable_by(|a, b| a.frequency.total_cmp(&b.frequency)),
+        }
+
+        self.pattern.clear();
+        for octave in 0..self.params.octaves.clamp(1, MAX_OCTAVES) {
+            let multiplier = (1 << octave) as f32;
+            self.pattern.extend(self.notes.iter().map(|note| ArpNote {
+                frequency: note.frequency * multiplier,
+                velocity: note.velocity,
+            }));
+        }
+
+        match self.params.order {
+            ArpOrder::Down => self.pattern.reverse(),
+            ArpOrder::UpDown => {
+                for i in (1..self.pattern.len().saturating_sub(1)).rev() {
+                    self.pattern.push(self.pattern[i]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts the next note of the pattern, with its length worked out in whole
+    /// frames as in the sequencer
+    fn start_step(&mut self) {
+        let frames = self.params.rate.seconds(self.params.bpm.max(1.0)) * self.sample_rate as f64;
+        let ticks = (frames * TICKS_PER_FRAME as f64).round() as u64 + self.carry;
+
+        self.step_frames = (ticks / TICKS_PER_FRAME).max(1);
+        self.carry = ticks.saturating_sub(self.step_frames * TICKS_PER_FRAME);
+        self.elapsed = 0;
+
+        self.note = if self.pattern.is_empty() {
+            self.position = 0;
+            None
+        } else {
+            let index = match self.params.order {
+                ArpOrder::Random => self.rng.next_below(self.pattern.len() as u32) as usize,
+                _ => self.position % self.pattern.len(),
+            };
+            self.position = index + 1;
+            Some(self.pattern[index])
+        };
+
+        self.gate_frames = match self.note {
+            Some(_) => {
+                let percent = self.params.gate_percent.clamp(0.0, 100.0) as f64;
+                ((self.step_frames as f64 * percent / 100.0).round() as u64).min(self.step_frames)
+            }
+            None => 0,
+        };
+    }
+
+    /// Rebuilds the pattern after the held notes or the order change, starting
+    /// straight away if nothing was playing
+    fn notes_changed(&mut self) {
+        let was_empty = self.pattern.is_empty();
+        self.build_pattern();
+
+        if was_empty && !self.pattern.is_empty() {
+            self.carry = 0;
+            self.start_step();
+        }
+    }
+
+    /// Plays the next `frames` frames into `outputs`, in the order of the
+    /// sequencer's outputs
+    fn render(&mut self, outputs: &mut [&mut [f32]], frames: usize) {
+        let mut current_sample_idx = 0;
+
+        while current_sample_idx < frames {
+            let gate = self.note.is_some() && self.elapsed < self.gate_frames;
+            let segment = if gate {
+                self.gate_frames - self.elapsed
+            } else {
+                self.step_frames - self.elapsed
+            };
+            let samples = (segment as usize).min(frames - current_sample_idx);
+            let range = current_sample_idx..current_sample_idx + samples;
+
+            let pitch = self
+                .pitch
+                .voltage(self.note.map_or(0.0, |note| note.frequency));
+            let velocity = self.note.map_or(0.0, |note| note.velocity);
+
+            outputs[PITCH_OUTPUT as usize][range.clone()].fill(pitch);
+            outputs[GATE_OUTPUT as usize][range.clone()].fill(if gate { 1.0 } else { 0.0 });
+            outputs[TRIGGER_OUTPUT as usize][range.clone()].fill(0.0);
+            outputs[VELOCITY_OUTPUT as usize][range.clone()].fill(velocity);
+            outputs[ACCENT_OUTPUT as usize][range].fill(0.0);
+
+            if gate && self.elapsed == 0 {
+                outputs[TRIGGER_OUTPUT as usize][current_sample_idx] = 1.0;
+            }
+
+            current_sample_idx += samples;
+            self.elapsed += samples as u64;
+
+            if self.elapsed >= self.step_frames {
+                self.start_step();
+            }
+        }
+    }
+}
+
+impl AudioNodeProcessor for ArpeggiatorProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        let mut notes_changed = false;
+
+        for mut event in events.drain() {
+            if let Some(patch) = ArpeggiatorNode::patch_event(&event) {
+                match patch {
+                    ArpeggiatorNodePatch::Latch(latch) => self.set_latch(latch),
+                    patch => self.params.apply(patch),
+                }
+                // the new values take effect from the next note
+                notes_changed = true;
+            } else if let Some(event) = event.downcast_mut::<ArpeggiatorEvent>() {
+                if let Err(e) = self.handle_event(event) {
+                    let _ = logger.try_error(e);
+                }
+                notes_changed = true;
+            }
+        }
+
+        // the chord inputs are read once per block, like events, as a pitch and a
+        // gate each
+        let is_active = |i: usize| {
+            proc_info.in_connected_mask.is_channel_connected(i)
+                && !proc_info.in_silence_mask.is_channel_silent(i)
+        };
+        let chord_inputs = buffers.inputs.len() / 2;
+        let mut chord = [0.0; MAX_CHORD_INPUTS as usize];
+        for (i, frequency) in chord.iter_mut().enumerate().take(chord_inputs) {
+            let (pitch, gate) = (i * 2, i * 2 + 1);
+            if is_active(gate) && buffers.inputs[gate][0] > 0.5 {
+                let voltage = if is_active(pitch) {
+                    buffers.inputs[pitch][0]
+                } else {
+                    0.0
+                };
+                *frequency = voltage_to_frequency(voltage);
+            }
+        }
+        if chord[..chord_inputs] != self.chord[..chord_inputs] {
+            if let Err(e) = self.update_chord(&chord[..chord_inputs]) {
+                let _ = logger.try_error(e);
+            }
+            notes_changed = true;
+        }
+
+        if notes_changed {
+            self.notes_changed();
+        }
+
+        self.render(buffers.outputs, proc_info.frames);
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.sample_rate = stream_info.sample_rate.into();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cv::frequency_to_voltage, tempo::NoteValue};
+
+    fn processor(order: ArpOrder, octaves: u32) -> ArpeggiatorProcessor {
+        let params = ArpeggiatorNode {
+            order,
+            octaves,
+            ..Default::default()
+        };
+        ArpeggiatorProcessor::new(&params, &ArpeggiatorConfig::default(), 48_000)
+    }
+
+    /// Holds a chord of notes in the order given
+    fn hold(processor: &mut ArpeggiatorProcessor, frequencies: &[f32]) {
+        for (key, frequency) in frequencies.iter().enumerate() {
+            processor.note_on(key as u32, *frequency, 1.0).unwrap();
+        }
+        processor.notes_changed();
+    }
+
+    fn pattern(processor: &ArpeggiatorProcessor) -> Vec<f32> {
+        processor
+            .pattern
+            .iter()
+            .map(|note| note.frequency)
+            .collect()
+    }
+
+    #[test]
+    fn test_orders() {
+        let chord = [300.0, 100.0, 200.0];
+
+        let mut up = processor(ArpOrder::Up, 2);
+        hold(&mut up, &chord);
+        assert_eq!(pattern(&up), [100.0, 200.0, 300.0, 200.0, 400.0, 600.0]);
+
+        let mut down = processor(ArpOrder::Down, 1);
+        hold(&mut down, &chord);
+        assert_eq!(pattern(&down), [300.0, 200.0, 100.0]);
+
+        let mut up_down = processor(ArpOrder::UpDown, 1);
+        hold(&mut up_down, &chord);
+        assert_eq!(pattern(&up_down), [100.0, 200.0, 300.0, 200.0]);
+
+        let mut as_played = processor(ArpOrder::AsPlayed, 1);
+        hold(&mut as_played, &chord);
+        assert_eq!(pattern(&as_played), chord);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut arp = processor(ArpOrder::Up, 1);
+        arp.set_latch(true);
+        hold(&mut arp, &[100.0, 200.0]);
+
+        // the notes keep playing after they are released
+        arp.note_off(0);
+        arp.note_off(1);
+        arp.notes_changed();
+        assert_eq!(pattern(&arp), [100.0, 200.0]);
+
+        // a new chord replaces them once every note has been let go
+        arp.note_on(5, 500.0, 1.0).unwrap();
+        arp.note_on(6, 600.0, 1.0).unwrap();
+        arp.notes_changed();
+        assert_eq!(pattern(&arp), [500.0, 600.0]);
+
+        // turning latch off lets go of the notes that aren't held down
+        arp.note_off(5);
+        arp.set_latch(false);
+        arp.notes_changed();
+        assert_eq!(pattern(&arp), [600.0]);
+    }
+
+    #[test]
+    fn test_notes_follow_the_rate() {
+        let mut arp = processor(ArpOrder::Up, 1);
+        arp.params.rate = NoteValue::EIGHTH;
+
+        // an eighth at 120 bpm and 48 kHz is 12000 frames, with the gate held for
+        // half of it
+        let frames = 24_000;
+        let mut buffers = vec![vec![0.0; frames]; 5];
+        let mut outputs: Vec<&mut [f32]> = buffers.iter_mut().map(Vec::as_mut_slice).collect();
+
+        hold(&mut arp, &[100.0, 200.0]);
+        arp.render(&mut outputs, frames);
+
+        let trigger = &buffers[TRIGGER_OUTPUT as usize];
+        let triggers: Vec<usize> = (0..frames).filter(|i| trigger[*i] > 0.0).collect();
+        assert_eq!(triggers, [0, 12_000]);
+
+        let gate = &buffers[GATE_OUTPUT as usize];
+        assert_eq!((gate[5_999], gate[6_000]), (1.0, 0.0));
+
+        let pitch = &buffers[PITCH_OUTPUT as usize];
+        assert_eq!(
+            (pitch[0], pitch[12_000]),
+            (frequency_to_voltage(100.0), frequency_to_voltage(200.0))
+        );
+    }
+}
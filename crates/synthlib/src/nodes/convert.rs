@@ -0,0 +1,203 @@
+use firewheel::{
+    SilenceMask,
+    channel_config::{ChannelConfig, NonZeroChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+};
+
+use crate::cv::{HeldPitch, voltage_to_frequency};
+
+/// Converts a frequency in hertz, for example from a source outside of this
+/// crate, to pitch in volts per octave (see [crate::cv]).
+///
+/// While the input is at 0 Hz, such as during a pause, the last voltage is held.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct HzToCvNode;
+
+/// Converts pitch in volts per octave (see [crate::cv]) to a frequency in hertz,
+/// for anything that needs a frequency rather than a pitch.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct CvToHzNode {
+    /// Added to the input before it is converted, in volts
+    pub offset: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertConfig {
+    /// The number of signals to convert, each with its own input and output
+    pub channels: NonZeroChannelCount,
+}
+
+impl Default for ConvertConfig {
+    fn default() -> Self {
+        Self {
+            channels: NonZeroChannelCount::MONO,
+        }
+    }
+}
+
+impl ConvertConfig {
+    fn channel_config(&self) -> ChannelConfig {
+        ChannelConfig {
+            num_inputs: self.channels.get(),
+            num_outputs: self.channels.get(),
+        }
+    }
+}
+
+impl AudioNode for HzToCvNode {
+    type Configuration = ConvertConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("hz_to_cv")
+            .channel_config(configuration.channel_config())
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        _cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        HzToCvProcessor {
+            pitches: vec![HeldPitch::default(); configuration.channels.get().get() as usize],
+        }
+    }
+}
+
+struct HzToCvProcessor {
+    /// The pitch of each channel, which is held while the input is at 0 Hz
+    pitches: Vec<HeldPitch>,
+}
+
+impl AudioNodeProcessor for HzToCvProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        _events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        let frames = proc_info.frames;
+
+        for ((input, output), pitch) in buffers
+            .inputs
+            .iter()
+            .zip(buffers.outputs.iter_mut())
+            .zip(self.pitches.iter_mut())
+        {
+            hz_to_cv(&input[..frames], &mut output[..frames], pitch);
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+}
+
+impl AudioNode for CvToHzNode {
+    type Configuration = ConvertConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("cv_to_hz")
+            .channel_config(configuration.channel_config())
+    }
+
+    fn construct_processor(
+        &self,
+        _configuration: &Self::Configuration,
+        _cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        CvToHzProcessor { params: *self }
+    }
+}
+
+struct CvToHzProcessor {
+    params: CvToHzNode,
+}
+
+impl AudioNodeProcessor for CvToHzProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<CvToHzNode>() {
+            self.params.apply(patch);
+        }
+
+        let frames = proc_info.frames;
+
+        for (input, output) in buffers.inputs.iter().zip(buffers.outputs.iter_mut()) {
+            cv_to_hz(&input[..frames], &mut output[..frames], self.params.offset);
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+}
+
+/// Converts a channel of frequencies to voltages, holding `pitch` while the
+/// input is at or below 0 Hz
+#[inline]
+fn hz_to_cv(input: &[f32], output: &mut [f32], pitch: &mut HeldPitch) {
+    for (input, output) in input.iter().zip(output.iter_mut()) {
+        *output = pitch.voltage(*input);
+    }
+}
+
+/// Converts a channel of voltages to frequencies, adding `offset` first
+#[inline]
+fn cv_to_hz(input: &[f32], output: &mut [f32], offset: f32) {
+    for (input, output) in input.iter().zip(output.iter_mut()) {
+        *output = voltage_to_frequency(*input + offset);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cv::{CV_REFERENCE_HZ, frequency_to_voltage};
+
+    #[test]
+    fn test_round_trip() {
+        let frequencies = [27.5, 110.0, CV_REFERENCE_HZ, 440.0, 4_186.0];
+        let mut voltages = [0.0; 5];
+        let mut output = [0.0; 5];
+
+        hz_to_cv(&frequencies, &mut voltages, &mut HeldPitch::default());
+        assert_eq!(voltages[2], 0.0);
+        assert!((voltages[1] - (voltages[3] - 2.0)).abs() < 1e-6);
+
+        cv_to_hz(&voltages, &mut output, 0.0);
+        for (output, frequency) in output.iter().zip(frequencies) {
+            assert!((output - frequency).abs() / frequency < 1e-5);
+        }
+
+        // the offset moves the pitch by whole octaves
+        cv_to_hz(&voltages, &mut output, 1.0);
+        assert!((output[3] - 880.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_holds_without_a_pitch() {
+        let mut pitch = HeldPitch::new(0.5);
+        let mut output = [9.0; 5];
+
+        // nothing has played yet, so the starting voltage is held
+        hz_to_cv(&[0.0, 440.0, 0.0, -10.0, 220.0], &mut output, &mut pitch);
+        let a4 = frequency_to_voltage(440.0);
+        assert_eq!(output[..4], [0.5, a4, a4, a4]);
+        assert!((output[4] - (a4 - 1.0)).abs() < 1e-6);
+        assert_eq!(pitch.voltage(0.0), output[4]);
+    }
+}
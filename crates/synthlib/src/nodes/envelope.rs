@@ -0,0 +1,488 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, EmptyConfig,
+        ProcBuffers, ProcInfo, ProcessStatus,
+    },
+};
+
+/// Gate and trigger inputs are considered high when they are above this value
+pub const GATE_THRESHOLD: f32 = 0.5;
+
+/// An envelope generator that outputs a `0.0` to `1.0` control signal, which can be
+/// used to modulate amplitude, filter cutoff or any other control input.
+///
+/// The envelope is driven by the gate input (channel 0), the trigger input (channel 1)
+/// or by sending [EnvelopeEvent]s to the node. A rising gate starts the envelope, a
+/// falling gate releases it and a trigger pulse restarts it while the gate is held.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct EnvelopeNode {
+    /// The shape of the envelope
+    pub adsr: Adsr,
+    /// How the envelope restarts when it is triggered before it has finished
+    pub retrigger: Retrigger,
+    /// When enabled, a new note while the gate is already high does not restart
+    /// the envelope.
+    pub legato: bool,
+}
+
+/// The stages of an envelope. All times are in milliseconds.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct Adsr {
+    /// The time to wait after the gate opens before the attack starts
+    pub delay_ms: f32,
+    /// The time to rise from `0.0` to `1.0`
+    pub attack_ms: f32,
+    /// The time to stay at `1.0` before decaying
+    pub hold_ms: f32,
+    /// The time to fall from `1.0` to `0.0`. The decay stops at the sustain level.
+    pub decay_ms: f32,
+    /// The level held while the gate is high, in the range `[0.0, 1.0]`
+    pub sustain: f32,
+    /// The time to fall from `1.0` to `0.0` after the gate closes
+    pub release_ms: f32,
+    /// The shape of the attack, decay and release stages
+    pub curve: EnvelopeCurve,
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self {
+            delay_ms: 0.0,
+            attack_ms: 10.0,
+            hold_ms: 0.0,
+            decay_ms: 100.0,
+            sustain: 0.7,
+            release_ms: 250.0,
+            curve: EnvelopeCurve::default(),
+        }
+    }
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvelopeCurve {
+    /// Each stage changes at a constant rate
+    Linear,
+    /// Each stage changes quickly at first and then slows, like an analog envelope
+    #[default]
+    Exponential,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retrigger {
+    /// The attack starts again from the current level, avoiding clicks
+    #[default]
+    Continue,
+    /// The level jumps back to `0.0` before the attack starts again
+    Restart,
+}
+
+/// Events that can be sent to an [EnvelopeNode] with `NodeEventType::custom`,
+/// as an alternative to driving it with the gate input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvelopeEvent {
+    NoteOn { velocity: f32 },
+    NoteOff,
+}
+
+impl AudioNode for EnvelopeNode {
+    type Configuration = EmptyConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("envelope")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::STEREO,
+                num_outputs: ChannelCount::MONO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        _configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        let mut envelope = Envelope::new(self.adsr, u32::from(cx.stream_info.sample_rate) as f32);
+        envelope.retrigger = self.retrigger;
+        envelope.legato = self.legato;
+
+        EnvelopeProcessor {
+            envelope,
+            gate: false,
+            trigger: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+}
+
+// Larger ratios make the curve closer to a straight line, smaller ratios make it
+// more exponential. The stage still reaches its target in the configured time.
+const LINEAR_RATIO: f32 = 1_000.0;
+const ATTACK_RATIO: f32 = 0.3;
+const DECAY_RATIO: f32 = 0.0001;
+
+/// Coefficients for a single curved stage of an envelope
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StageCoeffs {
+    base: f32,
+    coeff: f32,
+}
+
+impl StageCoeffs {
+    fn new(samples: f32, target: f32, ratio: f32, rising: bool) -> Self {
+        if samples < 1.0 {
+            // jump straight to the target
+            return Self {
+                base: target,
+                coeff: 0.0,
+            };
+        }
+
+        let coeff = (-((1.0 + ratio) / ratio).ln() / samples).exp();
+        let overshoot = if rising {
+            target + ratio
+        } else {
+            target - ratio
+        };
+
+        Self {
+            base: overshoot * (1.0 - coeff),
+            coeff,
+        }
+    }
+
+    #[inline]
+    fn next(&self, level: f32) -> f32 {
+        self.base + level * self.coeff
+    }
+}
+
+/// A DAHDSR envelope generator that can be used inside other processors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub retrigger: Retrigger,
+    pub legato: bool,
+    adsr: Adsr,
+    sample_rate: f32,
+    stage: Stage,
+    level: f32,
+    velocity: f32,
+    gate: bool,
+    /// The number of samples left in the delay or hold stages
+    samples_left: u32,
+    attack: StageCoeffs,
+    decay: StageCoeffs,
+    release: StageCoeffs,
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr, sample_rate: f32) -> Self {
+        let mut envelope = Self {
+            retrigger: Retrigger::default(),
+            legato: false,
+            adsr,
+            sample_rate,
+            stage: Stage::Idle,
+            level: 0.0,
+            velocity: 1.0,
+            gate: false,
+            samples_left: 0,
+            attack: StageCoeffs {
+                base: 0.0,
+                coeff: 0.0,
+            },
+            decay: StageCoeffs {
+                base: 0.0,
+                coeff: 0.0,
+            },
+            release: StageCoeffs {
+                base: 0.0,
+                coeff: 0.0,
+            },
+        };
+
+        envelope.set_adsr(adsr);
+        envelope
+    }
+
+    pub fn adsr(&self) -> &Adsr {
+        &self.adsr
+    }
+
+    pub fn set_adsr(&mut self, adsr: Adsr) {
+        self.adsr = adsr;
+        self.adsr.sustain = adsr.sustain.clamp(0.0, 1.0);
+
+        let (attack_ratio, decay_ratio) = match adsr.curve {
+            EnvelopeCurve::Linear => (LINEAR_RATIO, LINEAR_RATIO),
+            EnvelopeCurve::Exponential => (ATTACK_RATIO, DECAY_RATIO),
+        };
+
+        self.attack = StageCoeffs::new(self.ms_to_samples(adsr.attack_ms), 1.0, attack_ratio, true);
+        self.decay = StageCoeffs::new(
+            self.ms_to_samples(adsr.decay_ms),
+            self.adsr.sustain,
+            decay_ratio,
+            false,
+        );
+        self.release =
+            StageCoeffs::new(self.ms_to_samples(adsr.release_ms), 0.0, decay_ratio, false);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set_adsr(self.adsr);
+    }
+
+    fn ms_to_samples(&self, ms: f32) -> f32 {
+        ms.max(0.0) * 0.001 * self.sample_rate
+    }
+
+    /// Opens the gate, starting the envelope from the delay stage. Velocity scales
+    /// the output of the envelope.
+    pub fn gate_on(&mut self, velocity: f32) {
+        let was_open = self.gate;
+        self.gate = true;
+        self.velocity = velocity.clamp(0.0, 1.0);
+
+        if was_open && self.legato {
+            return;
+        }
+
+        self.trigger();
+    }
+
+    /// Restarts the envelope without changing the gate
+    pub fn trigger(&mut self) {
+        if self.retrigger == Retrigger::Restart {
+            self.level = 0.0;
+        }
+
+        self.samples_left = self.ms_to_samples(self.adsr.delay_ms) as u32;
+        self.stage = Stage::Delay;
+    }
+
+    /// Closes the gate, moving the envelope to its release stage
+    pub fn gate_off(&mut self) {
+        self.gate = false;
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+        }
+    }
+
+    /// Silences the envelope immediately
+    pub fn reset(&mut self) {
+        self.gate = false;
+        self.level = 0.0;
+        self.stage = Stage::Idle;
+    }
+
+    pub fn is_gate_open(&self) -> bool {
+        self.gate
+    }
+
+    /// An envelope is active until it has finished its release stage
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    /// The current output of the envelope, including velocity
+    pub fn level(&self) -> f32 {
+        self.level * self.velocity
+    }
+
+    /// Advances the envelope by one sample and returns the new output
+    #[inline]
+    pub fn next_sample(&mut self) -> f32 {
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Delay => {
+                if self.samples_left == 0 {
+                    self.stage = Stage::Attack;
+                } else {
+                    self.samples_left -= 1;
+                }
+            }
+            Stage::Attack => {
+                self.level = self.attack.next(self.level);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.samples_left = self.ms_to_samples(self.adsr.hold_ms) as u32;
+                    self.stage = Stage::Hold;
+                }
+            }
+            Stage::Hold => {
+                if self.samples_left == 0 {
+                    self.stage = Stage::Decay;
+                } else {
+                    self.samples_left -= 1;
+                }
+            }
+            Stage::Decay => {
+                self.level = self.decay.next(self.level);
+                if self.level <= self.adsr.sustain {
+                    self.level = self.adsr.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {
+                self.level = self.adsr.sustain;
+            }
+            Stage::Release => {
+                self.level = self.release.next(self.level);
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+
+        self.level * self.velocity
+    }
+}
+
+struct EnvelopeProcessor {
+    envelope: Envelope,
+    /// The state of the gate and trigger inputs at the end of the last block
+    gate: bool,
+    trigger: bool,
+}
+
+impl AudioNodeProcessor for EnvelopeProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for event in events.drain() {
+            if let Some(patch) = EnvelopeNode::patch_event(&event) {
+                match patch {
+                    EnvelopeNodePatch::Adsr(patch) => {
+                        let mut adsr = *self.envelope.adsr();
+                        adsr.apply(patch);
+                        self.envelope.set_adsr(adsr);
+                    }
+                    EnvelopeNodePatch::Retrigger(retrigger) => self.envelope.retrigger = retrigger,
+                    EnvelopeNodePatch::Legato(legato) => self.envelope.legato = legato,
+                }
+            } else if let Some(event) = event.downcast_ref::<EnvelopeEvent>() {
+                match *event {
+                    EnvelopeEvent::NoteOn { velocity } => self.envelope.gate_on(velocity),
+                    EnvelopeEvent::NoteOff => self.envelope.gate_off(),
+                }
+            }
+        }
+
+        let inputs_silent = proc_info.in_silence_mask.all_channels_silent(2);
+        if inputs_silent && !self.gate && !self.envelope.is_active() {
+            self.trigger = false;
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let gate_in = &buffers.inputs[0][..proc_info.frames];
+        let trigger_in = &buffers.inputs[1][..proc_info.frames];
+        let out = &mut buffers.outputs[0][..proc_info.frames];
+
+        for i in 0..proc_info.frames {
+            let gate = gate_in[i] > GATE_THRESHOLD;
+            let trigger = trigger_in[i] > GATE_THRESHOLD;
+
+            if gate && !self.gate {
+                self.envelope.gate_on(1.0);
+            } else if !gate && self.gate {
+                self.envelope.gate_off();
+            } else if gate && trigger && !self.trigger && !self.envelope.legato {
+                self.envelope.trigger();
+            }
+
+            self.gate = gate;
+            self.trigger = trigger;
+
+            out[i] = self.envelope.next_sample();
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.envelope
+            .set_sample_rate(u32::from(stream_info.sample_rate) as f32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(envelope: &mut Envelope, samples: usize) -> f32 {
+        for _ in 0..samples {
+            envelope.next_sample();
+        }
+        envelope.level()
+    }
+
+    #[test]
+    fn test_stage_times() {
+        for curve in [EnvelopeCurve::Linear, EnvelopeCurve::Exponential] {
+            let mut envelope = Envelope::new(
+                Adsr {
+                    delay_ms: 10.0,
+                    attack_ms: 10.0,
+                    hold_ms: 10.0,
+                    decay_ms: 10.0,
+                    sustain: 0.5,
+                    release_ms: 10.0,
+                    curve,
+                },
+                1000.0,
+            );
+
+            envelope.gate_on(1.0);
+            assert_eq!(run(&mut envelope, 10), 0.0, "{curve:?} delay");
+            assert_eq!(run(&mut envelope, 11), 1.0, "{curve:?} attack");
+            assert_eq!(run(&mut envelope, 10), 1.0, "{curve:?} hold");
+            assert_eq!(run(&mut envelope, 12), 0.5, "{curve:?} sustain");
+
+            envelope.gate_off();
+            assert_eq!(run(&mut envelope, 11), 0.0, "{curve:?} release");
+            assert!(!envelope.is_active());
+        }
+    }
+
+    #[test]
+    fn test_legato() {
+        let mut envelope = Envelope::new(Adsr::default(), 1000.0);
+        envelope.legato = true;
+
+        envelope.gate_on(1.0);
+        run(&mut envelope, 1000);
+        let sustain = envelope.level();
+
+        // a second note on while the gate is held keeps the sustain level
+        envelope.gate_on(1.0);
+        assert_eq!(run(&mut envelope, 1), sustain);
+
+        envelope.legato = false;
+        envelope.retrigger = Retrigger::Restart;
+        envelope.gate_on(1.0);
+        assert!(run(&mut envelope, 1) < sustain);
+    }
+}
@@ -1,6 +1,9 @@
 //! NOTE - COPIED FROM https://github.com/BillyDM/Firewheel/blob/main/examples/custom_nodes/src/nodes/filter.rs
 //! This node applies a simple single-pole lowpass filter to a stereo signal.
 //!
+//! The cutoff can also be modulated at audio rate from a control input, and
+//! follow the pitch of a note from a frequency input for keyboard tracking.
+//!
 //! It also demonstrates how to make proper use of the parameter smoothers and
 //! declickers from the dsp module, as well as how to make proper use of the
 //! silence flags for optimization.
@@ -8,22 +11,24 @@
 use std::f32::consts::PI;
 
 use firewheel::{
-    SilenceMask, StreamInfo,
-    channel_config::{ChannelConfig, ChannelCount},
+    ConnectedMask, SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount, NonZeroChannelCount},
     diff::{Diff, Patch},
     dsp::{
-        declick::{Declicker, FadeType},
+        declick::{DeclickValues, Declicker, FadeType},
         volume::{DEFAULT_AMP_EPSILON, Volume},
     },
     event::NodeEventList,
     log::RealtimeLogger,
     node::{
-        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, EmptyConfig,
-        ProcBuffers, ProcInfo, ProcessStatus,
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
     },
     param::smoother::{SmoothedParam, SmoothedParamBuffer},
 };
 
+use super::upmix::{bypass, is_upmixing, stereo_inputs};
+
 // The node struct holds all of the parameters of the node as plain values.
 ///
 /// # Notes about ECS
@@ -37,6 +42,12 @@ use firewheel::{
 pub struct FilterNode {
     /// The cutoff frequency in hertz in the range `[20.0, 20_000.0]`.
     pub cutoff_hz: f32,
+    /// How far the cutoff moves, in octaves, for a value of `1.0` on the
+    /// modulation input.
+    pub mod_depth: f32,
+    /// How closely the cutoff follows the key input. At `1.0` the cutoff moves one
+    /// octave for every octave the note is away from middle C.
+    pub key_tracking: f32,
     /// The overall volume.
     pub volume: Volume,
     /// Whether or not this node is enabled.
@@ -47,30 +58,70 @@ impl Default for FilterNode {
     fn default() -> Self {
         Self {
             cutoff_hz: 1.0,
+            mod_depth: 1.0,
+            key_tracking: 0.0,
             volume: Volume::default(),
             enabled: true,
         }
     }
 }
 
+/// The configuration of a [FilterNode].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FilterConfig {
+    /// Whether the filter runs in mono or stereo. In stereo, a mono source that
+    /// is only connected to the left input is played on both channels.
+    pub channels: Channels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Channels {
+    Mono,
+    #[default]
+    Stereo,
+}
+
+impl Channels {
+    pub fn count(&self) -> NonZeroChannelCount {
+        match self {
+            Self::Mono => NonZeroChannelCount::MONO,
+            Self::Stereo => NonZeroChannelCount::STEREO,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// The input channel for the cutoff modulation control signal, which comes
+    /// straight after the audio inputs.
+    pub fn mod_input(&self) -> u32 {
+        self.channels.count().get().get()
+    }
+
+    /// The input channel for the pitch used for keyboard tracking, in volts per
+    /// octave (see [crate::cv]), for example from a
+    /// [crate::nodes::sequencer::SequencerNode].
+    pub fn key_input(&self) -> u32 {
+        self.mod_input() + 1
+    }
+}
+
 // Implement the AudioNode type for your node.
 impl AudioNode for FilterNode {
-    // Since this node doesnt't need any configuration, we'll just
-    // default to `EmptyConfig`.
-    type Configuration = EmptyConfig;
+    type Configuration = FilterConfig;
 
     // Return information about your node. This method is only ever called
     // once.
-    fn info(&self, _config: &Self::Configuration) -> AudioNodeInfo {
+    fn info(&self, config: &Self::Configuration) -> AudioNodeInfo {
         // The builder pattern is used for future-proofness as it is likely that
         // more fields will be added in the future.
         AudioNodeInfo::new()
             // A static name used for debugging purposes.
             .debug_name("example_filter")
-            // The configuration of the input/output ports.
+            // The configuration of the input/output ports. The audio inputs are
+            // followed by the modulation and key tracking inputs.
             .channel_config(ChannelConfig {
-                num_inputs: ChannelCount::STEREO,
-                num_outputs: ChannelCount::STEREO,
+                num_inputs: ChannelCount::new(config.key_input() + 1).unwrap(),
+                num_outputs: config.channels.count().get(),
             })
     }
 
@@ -81,132 +132,145 @@ impl AudioNode for FilterNode {
     // thread, so it is safe to do non-realtime things here like allocating.
     fn construct_processor(
         &self,
-        _config: &Self::Configuration,
+        config: &Self::Configuration,
         cx: ConstructProcessorContext,
     ) -> impl AudioNodeProcessor {
-        // The reciprocal of the sample rate.
-        let sample_rate_recip = cx.stream_info.sample_rate_recip as f32;
-
-        let cutoff_hz = self.cutoff_hz.clamp(20.0, 20_000.0);
-        let gain = self.volume.amp_clamped(DEFAULT_AMP_EPSILON);
-
-        Processor {
-            filter_l: OnePoleLPBiquad::new(cutoff_hz, sample_rate_recip),
-            filter_r: OnePoleLPBiquad::new(cutoff_hz, sample_rate_recip),
-            cutoff_hz: SmoothedParam::new(
-                cutoff_hz,
-                Default::default(),
-                cx.stream_info.sample_rate,
-            ),
-            gain: SmoothedParamBuffer::new(gain, Default::default(), cx.stream_info),
-            enable_declicker: Declicker::from_enabled(self.enabled),
-            sample_rate_recip,
-        }
+        Processor::new(self, config, cx.stream_info)
     }
 }
 
 // The realtime processor counterpart to your node.
 struct Processor {
-    filter_l: OnePoleLPBiquad,
-    filter_r: OnePoleLPBiquad,
+    // One filter per channel, only the first is used in mono.
+    filters: [OnePoleLPBiquad; 2],
+    channels: usize,
     // A helper struct to smooth a parameter.
     cutoff_hz: SmoothedParam,
     // This is similar to `SmoothedParam`, but it also contains an allocated buffer
     // for the smoothed values.
     gain: SmoothedParamBuffer,
+    mod_depth: f32,
+    key_tracking: f32,
     // This struct is used to declick when enabling/disabling this node.
     enable_declicker: Declicker,
     sample_rate_recip: f32,
 }
 
-impl AudioNodeProcessor for Processor {
-    // The realtime process method.
-    fn process(
+impl Processor {
+    fn new(node: &FilterNode, config: &FilterConfig, stream_info: &StreamInfo) -> Self {
+        // The reciprocal of the sample rate.
+        let sample_rate_recip = stream_info.sample_rate_recip as f32;
+
+        let cutoff_hz = node.cutoff_hz.clamp(20.0, 20_000.0);
+        let gain = node.volume.amp_clamped(DEFAULT_AMP_EPSILON);
+
+        Self {
+            filters: [
+                OnePoleLPBiquad::new(cutoff_hz, sample_rate_recip),
+                OnePoleLPBiquad::new(cutoff_hz, sample_rate_recip),
+            ],
+            channels: config.channels.count().get().get() as usize,
+            cutoff_hz: SmoothedParam::new(cutoff_hz, Default::default(), stream_info.sample_rate),
+            gain: SmoothedParamBuffer::new(gain, Default::default(), stream_info),
+            mod_depth: node.mod_depth,
+            key_tracking: node.key_tracking,
+            enable_declicker: Declicker::from_enabled(node.enabled),
+            sample_rate_recip,
+        }
+    }
+
+    // Processes a block once the events have been handled. This takes the parts of
+    // `ProcInfo` it needs so that it can be tested without an audio graph.
+    fn render(
         &mut self,
-        // The buffers of data to process.
-        buffers: ProcBuffers,
-        // Additional information about the process.
-        proc_info: &ProcInfo,
-        // The list of events for our node to process.
-        events: &mut NodeEventList,
-        // A realtime-safe logger helper.
-        _logger: &mut RealtimeLogger,
+        inputs: &[&[f32]],
+        outputs: &mut [&mut [f32]],
+        frames: usize,
+        in_silence_mask: SilenceMask,
+        in_connected_mask: ConnectedMask,
+        declick_values: &DeclickValues,
     ) -> ProcessStatus {
-        // Process the events.
-        //
-        // We don't need to keep around a `FilterNode` instance,
-        // so we can just match on each event directly.
-        for patch in events.drain_patches::<FilterNode>() {
-            match patch {
-                FilterNodePatch::CutoffHz(cutoff) => {
-                    self.cutoff_hz.set_value(cutoff.clamp(20.0, 20_000.0));
-                }
-                FilterNodePatch::Volume(volume) => {
-                    self.gain.set_value(volume.amp_clamped(DEFAULT_AMP_EPSILON));
-                }
-                FilterNodePatch::Enabled(enabled) => {
-                    // Tell the declicker to crossfade.
-                    self.enable_declicker
-                        .fade_to_enabled(enabled, proc_info.declick_values);
-                }
-            }
-        }
+        // When running in stereo with nothing connected to the right input, the
+        // left input is a mono source that is played on both channels.
+        let upmix = is_upmixing(self.channels, in_connected_mask);
+        let active_channels = if upmix { 1 } else { self.channels };
 
         if self.enable_declicker.disabled() {
             // Disabled. Bypass this node.
-            return ProcessStatus::Bypass;
+            return bypass(inputs, outputs, frames, upmix);
         }
 
         // If the gain parameter is not currently smoothing and is silent, then
         // there is no need to process.
         let gain_is_silent = !self.gain.is_smoothing() && self.gain.target_value() < 0.00001;
 
-        if proc_info.in_silence_mask.all_channels_silent(2) || gain_is_silent {
+        if in_silence_mask.all_channels_silent(active_channels) || gain_is_silent {
             // Outputs will be silent, so no need to process.
 
             // Reset the smoothers and filters since they don't need to smooth any
             // output.
             self.cutoff_hz.reset();
             self.gain.reset();
-            self.filter_l.reset();
-            self.filter_r.reset();
+            self.filters.iter_mut().for_each(OnePoleLPBiquad::reset);
             self.enable_declicker.reset_to_target();
 
             return ProcessStatus::ClearAllOutputs;
         }
 
-        // Get slices of the input and output buffers.
-        //
-        // Doing it this way allows the compiler to better optimize the processing
-        // loops below.
-        let in1 = &buffers.inputs[0][..proc_info.frames];
-        let in2 = &buffers.inputs[1][..proc_info.frames];
-        let (out1, out2) = buffers.outputs.split_first_mut().unwrap();
-        let out1 = &mut out1[..proc_info.frames];
-        let out2 = &mut out2[0][..proc_info.frames];
-
         // Retrieve a buffer of the smoothed gain values.
         //
         // The redundant slicing is not strictly necessary, but it may help make sure
         // the compiler properly optimizes the below processing loops.
-        let gain = &self.gain.get_buffer(proc_info.frames).0[..proc_info.frames];
+        let gain = &self.gain.get_buffer(frames).0[..frames];
+
+        // The modulation inputs only need to be read when something is connected
+        // to them and they would actually move the cutoff.
+        let mod_input = self.channels;
+        let key_input = self.channels + 1;
+        let input_active = |channel: usize, amount: f32| {
+            amount != 0.0
+                && in_connected_mask.is_channel_connected(channel)
+                && !in_silence_mask.is_channel_silent(channel)
+        };
+        let mod_active = input_active(mod_input, self.mod_depth);
+        let key_active = input_active(key_input, self.key_tracking);
+
+        if mod_active || key_active {
+            let mod_in = &inputs[mod_input][..frames];
+            let key_in = &inputs[key_input][..frames];
+
+            // Envelope sweeps need to be sample accurate, so the coefficients are
+            // recalculated every frame while the cutoff is being modulated.
+            for i in 0..frames {
+                let cutoff_hz = modulate_cutoff(
+                    self.cutoff_hz.next_smoothed(),
+                    if mod_active { mod_in[i] } else { 0.0 },
+                    self.mod_depth,
+                    if key_active { key_in[i] } else { 0.0 },
+                    self.key_tracking,
+                );
+
+                set_cutoff(&mut self.filters, cutoff_hz, self.sample_rate_recip);
+
+                for ch in 0..active_channels {
+                    outputs[ch][i] = self.filters[ch].process(inputs[ch][i]) * gain[i];
+                }
+            }
 
-        if self.cutoff_hz.is_smoothing() {
-            for i in 0..proc_info.frames {
+            self.cutoff_hz.settle();
+        } else if self.cutoff_hz.is_smoothing() {
+            for i in 0..frames {
                 let cutoff_hz = self.cutoff_hz.next_smoothed();
 
                 // Because recalculating filter coefficients is expensive, a trick like
                 // this can be use to only recalculate them every 16 frames.
                 if i & (16 - 1) == 0 {
-                    self.filter_l.set_cutoff(cutoff_hz, self.sample_rate_recip);
-                    self.filter_r.copy_cutoff_from(&self.filter_l);
+                    set_cutoff(&mut self.filters, cutoff_hz, self.sample_rate_recip);
                 }
 
-                let fl = self.filter_l.process(in1[i]);
-                let fr = self.filter_r.process(in2[i]);
-
-                out1[i] = fl * gain[i];
-                out2[i] = fr * gain[i];
+                for ch in 0..active_channels {
+                    outputs[ch][i] = self.filters[ch].process(inputs[ch][i]) * gain[i];
+                }
             }
 
             // Settle the filter if its state is close enough to the target value.
@@ -215,25 +279,38 @@ impl AudioNodeProcessor for Processor {
         } else {
             // The cutoff parameter is not currently smoothing, so we can optimize by
             // only updating the filter coefficients once.
-            self.filter_l
-                .set_cutoff(self.cutoff_hz.target_value(), self.sample_rate_recip);
-            self.filter_r.copy_cutoff_from(&self.filter_l);
-
-            for i in 0..proc_info.frames {
-                let fl = self.filter_l.process(in1[i]);
-                let fr = self.filter_r.process(in2[i]);
-
-                out1[i] = fl * gain[i];
-                out2[i] = fr * gain[i];
+            set_cutoff(
+                &mut self.filters,
+                self.cutoff_hz.target_value(),
+                self.sample_rate_recip,
+            );
+
+            for ((filter, input), output) in self
+                .filters
+                .iter_mut()
+                .zip(inputs.iter())
+                .zip(outputs.iter_mut())
+                .take(active_channels)
+            {
+                let input = &input[..frames];
+                let output = &mut output[..frames];
+
+                for i in 0..frames {
+                    output[i] = filter.process(input[i]) * gain[i];
+                }
             }
         }
 
         // Crossfade between the wet and dry signals to declick enabling/disabling.
+        if upmix {
+            let (left, right) = outputs.split_first_mut().unwrap();
+            right[0][..frames].copy_from_slice(&left[..frames]);
+        }
         self.enable_declicker.process_crossfade(
-            buffers.inputs,
-            buffers.outputs,
-            proc_info.frames,
-            proc_info.declick_values,
+            &stereo_inputs(inputs, upmix)[..self.channels],
+            outputs,
+            frames,
+            declick_values,
             FadeType::EqualPower3dB,
         );
 
@@ -242,6 +319,52 @@ impl AudioNodeProcessor for Processor {
             out_silence_mask: SilenceMask::NONE_SILENT,
         }
     }
+}
+
+impl AudioNodeProcessor for Processor {
+    // The realtime process method.
+    fn process(
+        &mut self,
+        // The buffers of data to process.
+        buffers: ProcBuffers,
+        // Additional information about the process.
+        proc_info: &ProcInfo,
+        // The list of events for our node to process.
+        events: &mut NodeEventList,
+        // A realtime-safe logger helper.
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        // Process the events.
+        //
+        // We don't need to keep around a `FilterNode` instance,
+        // so we can just match on each event directly.
+        for patch in events.drain_patches::<FilterNode>() {
+            match patch {
+                FilterNodePatch::CutoffHz(cutoff) => {
+                    self.cutoff_hz.set_value(cutoff.clamp(20.0, 20_000.0));
+                }
+                FilterNodePatch::ModDepth(depth) => self.mod_depth = depth,
+                FilterNodePatch::KeyTracking(amount) => self.key_tracking = amount,
+                FilterNodePatch::Volume(volume) => {
+                    self.gain.set_value(volume.amp_clamped(DEFAULT_AMP_EPSILON));
+                }
+                FilterNodePatch::Enabled(enabled) => {
+                    // Tell the declicker to crossfade.
+                    self.enable_declicker
+                        .fade_to_enabled(enabled, proc_info.declick_values);
+                }
+            }
+        }
+
+        self.render(
+            buffers.inputs,
+            buffers.outputs,
+            proc_info.frames,
+            proc_info.in_silence_mask,
+            proc_info.in_connected_mask,
+            proc_info.declick_values,
+        )
+    }
 
     // Called when a new stream has been created. Because the new stream may have a
     // different sample rate from the old one, make sure to update any calculations
@@ -255,14 +378,40 @@ impl AudioNodeProcessor for Processor {
         self.cutoff_hz.update_sample_rate(stream_info.sample_rate);
         self.gain.update_stream(stream_info);
 
-        self.filter_l
-            .set_cutoff(self.cutoff_hz.target_value(), self.sample_rate_recip);
-        self.filter_r.copy_cutoff_from(&self.filter_l);
+        set_cutoff(
+            &mut self.filters,
+            self.cutoff_hz.target_value(),
+            self.sample_rate_recip,
+        );
     }
 }
 
+// Calculates the coefficients once and shares them between the channels.
+#[inline]
+fn set_cutoff(filters: &mut [OnePoleLPBiquad; 2], cutoff_hz: f32, sample_rate_recip: f32) {
+    let [l, r] = filters;
+    l.set_cutoff(cutoff_hz, sample_rate_recip);
+    r.copy_cutoff_from(l);
+}
+
+/// Applies the modulation and keyboard tracking to a cutoff frequency. The
+/// modulation value is scaled by `mod_depth` and the key pitch in volts per octave
+/// by `key_tracking`, and both are added in octaves.
+#[inline]
+pub fn modulate_cutoff(
+    cutoff_hz: f32,
+    modulation: f32,
+    mod_depth: f32,
+    key_voltage: f32,
+    key_tracking: f32,
+) -> f32 {
+    let octaves = modulation * mod_depth + key_voltage * key_tracking;
+
+    (cutoff_hz * octaves.exp2()).clamp(20.0, 20_000.0)
+}
+
 // A simple one pole lowpass biquad filter.
-struct OnePoleLPBiquad {
+pub(crate) struct OnePoleLPBiquad {
     a0: f32,
     b1: f32,
     z1: f32,
@@ -303,3 +452,118 @@ impl OnePoleLPBiquad {
         self.z1
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use super::*;
+
+    const FRAMES: usize = 16;
+
+    fn processor(channels: Channels, enabled: bool) -> Processor {
+        let node = FilterNode {
+            cutoff_hz: 20_000.0,
+            enabled,
+            ..Default::default()
+        };
+        Processor::new(&node, &FilterConfig { channels }, &StreamInfo::default())
+    }
+
+    /// Renders a block of a constant left input and silent right input, with the
+    /// modulation and key inputs disconnected, returning the left and right outputs
+    fn render(processor: &mut Processor, connected: u64) -> (ProcessStatus, [[f32; FRAMES]; 2]) {
+        let (left, right, cv) = ([1.0; FRAMES], [0.0; FRAMES], [0.0; FRAMES]);
+        let inputs: &[&[f32]] = match processor.channels {
+            1 => &[&left, &cv, &cv],
+            _ => &[&left, &right, &cv, &cv],
+        };
+        let mut outputs = [[9.0; FRAMES]; 2];
+        let [out_l, out_r] = &mut outputs;
+        let outputs_ref: &mut [&mut [f32]] = &mut [out_l, out_r];
+
+        let status = processor.render(
+            inputs,
+            &mut outputs_ref[..processor.channels],
+            FRAMES,
+            SilenceMask::NONE_SILENT,
+            ConnectedMask(connected),
+            &DeclickValues::new(NonZeroU32::MIN),
+        );
+        (status, outputs)
+    }
+
+    fn assert_filtered(output: &[f32]) {
+        // the filter has to rise towards the input before it settles on it
+        assert!(output[0] > 0.5 && output[0] < 1.0, "{output:?}");
+        assert!((output[FRAMES - 1] - 1.0).abs() < 1e-3, "{output:?}");
+    }
+
+    #[test]
+    fn test_mono() {
+        let mut filter = processor(Channels::Mono, true);
+        let (status, [left, right]) = render(&mut filter, 0b001);
+
+        assert_eq!(
+            status,
+            ProcessStatus::OutputsModified {
+                out_silence_mask: SilenceMask::NONE_SILENT
+            }
+        );
+        assert_filtered(&left);
+        // there is no right output to write to
+        assert_eq!(right, [9.0; FRAMES]);
+    }
+
+    #[test]
+    fn test_upmix() {
+        // a mono source on the left input is played on both channels
+        let mut filter = processor(Channels::Stereo, true);
+        let (_, [left, right]) = render(&mut filter, 0b0001);
+        assert_filtered(&left);
+        assert_eq!(left, right);
+
+        // with both inputs connected the silent right input stays silent
+        let mut filter = processor(Channels::Stereo, true);
+        let (_, [left, right]) = render(&mut filter, 0b0011);
+        assert_filtered(&left);
+        assert_eq!(right, [0.0; FRAMES]);
+    }
+
+    #[test]
+    fn test_bypass() {
+        let mut filter = processor(Channels::Stereo, false);
+        assert_eq!(render(&mut filter, 0b0011).0, ProcessStatus::Bypass);
+
+        let mut filter = processor(Channels::Mono, false);
+        assert_eq!(render(&mut filter, 0b001).0, ProcessStatus::Bypass);
+
+        // the engine's bypass would leave the right output silent, so a mono source
+        // is copied to both outputs instead
+        let mut filter = processor(Channels::Stereo, false);
+        let (status, outputs) = render(&mut filter, 0b0001);
+        assert_eq!(
+            status,
+            ProcessStatus::OutputsModified {
+                out_silence_mask: SilenceMask::NONE_SILENT
+            }
+        );
+        assert_eq!(outputs, [[1.0; FRAMES]; 2]);
+    }
+
+    #[test]
+    fn test_modulate_cutoff() {
+        assert_eq!(modulate_cutoff(1_000.0, 0.0, 2.0, 0.0, 1.0), 1_000.0);
+        assert_eq!(modulate_cutoff(1_000.0, 1.0, 2.0, 0.0, 1.0), 4_000.0);
+        assert_eq!(modulate_cutoff(1_000.0, -0.5, 2.0, 0.0, 1.0), 500.0);
+
+        // an octave above middle C doubles the cutoff with full tracking
+        assert_eq!(modulate_cutoff(1_000.0, 0.0, 1.0, 1.0, 1.0), 2_000.0);
+        let cutoff = modulate_cutoff(1_000.0, 0.0, 1.0, 1.0, 0.5);
+        assert!((cutoff - 1_414.21).abs() < 0.01);
+        assert_eq!(modulate_cutoff(1_000.0, 0.0, 1.0, -1.0, 1.0), 500.0);
+
+        assert_eq!(modulate_cutoff(1_000.0, 20.0, 1.0, 0.0, 0.0), 20_000.0);
+        assert_eq!(modulate_cutoff(1_000.0, -20.0, 1.0, 0.0, 0.0), 20.0);
+    }
+}
@@ -0,0 +1,152 @@
+use firewheel::{
+    SilenceMask,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, EmptyConfig,
+        ProcBuffers, ProcInfo, ProcessStatus,
+    },
+};
+
+/// The input channel for the signal that is held
+pub const SIGNAL_INPUT: u32 = 0;
+/// The input channel for the trigger or gate that controls when the signal is
+/// held
+pub const TRIGGER_INPUT: u32 = 1;
+
+/// The level above which the trigger input is high, halfway between the low and
+/// high outputs of a [crate::nodes::sequencer::SequencerNode]
+const TRIGGER_THRESHOLD: f32 = 0.5;
+
+/// Holds a control signal steady, for example to step an
+/// [crate::nodes::lfo::LfoNode] in time with a
+/// [crate::nodes::sequencer::SequencerNode]'s trigger output.
+///
+/// The first input is the signal ([SIGNAL_INPUT]) and the second is the trigger or
+/// gate ([TRIGGER_INPUT]), which is high above `0.5`.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct HoldNode {
+    pub mode: HoldMode,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HoldMode {
+    /// Samples the signal when the trigger goes high and holds it until the next
+    /// trigger
+    #[default]
+    SampleAndHold,
+    /// Follows the signal while the gate is high and holds the last value while
+    /// it is low
+    TrackAndHold,
+}
+
+/// The state of a [HoldNode]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hold {
+    value: f32,
+    high: bool,
+}
+
+impl Hold {
+    pub fn next(&mut self, mode: HoldMode, signal: f32, trigger: f32) -> f32 {
+        let high = trigger > TRIGGER_THRESHOLD;
+
+        let update = match mode {
+            HoldMode::SampleAndHold => high && !self.high,
+            HoldMode::TrackAndHold => high,
+        };
+        if update {
+            self.value = signal;
+        }
+
+        self.high = high;
+        self.value
+    }
+}
+
+impl AudioNode for HoldNode {
+    type Configuration = EmptyConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("hold")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::STEREO,
+                num_outputs: ChannelCount::MONO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        _configuration: &Self::Configuration,
+        _cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        HoldProcessor {
+            params: *self,
+            hold: Hold::default(),
+        }
+    }
+}
+
+struct HoldProcessor {
+    params: HoldNode,
+    hold: Hold,
+}
+
+impl AudioNodeProcessor for HoldProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<HoldNode>() {
+            self.params.apply(patch);
+        }
+
+        let frames = proc_info.frames;
+        let signal = &buffers.inputs[SIGNAL_INPUT as usize][..frames];
+        let trigger = &buffers.inputs[TRIGGER_INPUT as usize][..frames];
+        let output = &mut buffers.outputs[0][..frames];
+
+        for i in 0..frames {
+            output[i] = self.hold.next(self.params.mode, signal[i], trigger[i]);
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(mode: HoldMode, signal: &[f32], trigger: &[f32]) -> Vec<f32> {
+        let mut hold = Hold::default();
+        signal
+            .iter()
+            .zip(trigger)
+            .map(|(signal, trigger)| hold.next(mode, *signal, *trigger))
+            .collect()
+    }
+
+    #[test]
+    fn test_hold_modes() {
+        let signal = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let trigger = [0.0, 1.0, 1.0, 0.0, 0.0, 1.0];
+
+        assert_eq!(
+            run(HoldMode::SampleAndHold, &signal, &trigger),
+            [0.0, 2.0, 2.0, 2.0, 2.0, 6.0]
+        );
+        assert_eq!(
+            run(HoldMode::TrackAndHold, &signal, &trigger),
+            [0.0, 2.0, 3.0, 3.0, 3.0, 6.0]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! A nonlinear four-pole ladder filter in the style of the Moog transistor ladder.
+//! Each stage saturates, so driving the filter adds harmonics and the resonance can
+//! be pushed into self-oscillation. The filter runs oversampled to keep the aliasing
+//! from the nonlinearities down.
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    dsp::{
+        declick::{Declicker, FadeType},
+        volume::{DEFAULT_AMP_EPSILON, Volume},
+    },
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+    param::smoother::{SmoothedParam, SmoothedParamBuffer},
+};
+
+use super::upmix::{bypass, is_upmixing, stereo_inputs};
+use crate::nodes::svf::{Svf, SvfCoeffs, SvfMode};
+
+pub const MAX_RESONANCE: f32 = 1.2;
+pub const MAX_DRIVE: f32 = 10.0;
+
+/// A stereo four-pole (24 dB/octave) lowpass ladder filter with drive. A mono
+/// source that is only connected to the left input is played on both channels.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct LadderFilterNode {
+    /// The cutoff frequency in hertz in the range `[20.0, 20_000.0]`.
+    pub cutoff_hz: f32,
+    /// The resonance in the range `[0.0, 1.2]`. The filter self-oscillates from
+    /// around `1.0`.
+    pub resonance: f32,
+    /// The gain applied to the input before the first stage, in the range `[0.1, 10.0]`.
+    /// Higher values saturate the filter.
+    pub drive: f32,
+    /// How much of the low end lost to high resonance is restored, in the range
+    /// `[0.0, 1.0]`
+    pub bass_compensation: f32,
+    /// The overall volume.
+    pub volume: Volume,
+    /// Whether or not this node is enabled.
+    pub enabled: bool,
+}
+
+impl Default for LadderFilterNode {
+    fn default() -> Self {
+        Self {
+            cutoff_hz: 1_000.0,
+            resonance: 0.3,
+            drive: 1.0,
+            bass_compensation: 0.5,
+            volume: Volume::default(),
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LadderFilterConfig {
+    /// How many times the filter runs for each sample, in the range `[1, 8]`
+    pub oversampling: u32,
+}
+
+impl Default for LadderFilterConfig {
+    fn default() -> Self {
+        Self { oversampling: 4 }
+    }
+}
+
+impl AudioNode for LadderFilterNode {
+    type Configuration = LadderFilterConfig;
+
+    fn info(&self, _config: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("ladder_filter")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::STEREO,
+                num_outputs: ChannelCount::STEREO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        config: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        let oversampling = config.oversampling.clamp(1, 8);
+        let sample_rate_recip = cx.stream_info.sample_rate_recip as f32;
+        let cutoff_hz = self.cutoff_hz.clamp(20.0, 20_000.0);
+
+        let mut processor = Processor {
+            ladder_l: Ladder::default(),
+            ladder_r: Ladder::default(),
+            g: 0.0,
+            resonance: self.resonance.clamp(0.0, MAX_RESONANCE),
+            drive: self.drive.clamp(0.1, MAX_DRIVE),
+            bass_compensation: self.bass_compensation.clamp(0.0, 1.0),
+            anti_alias: SvfCoeffs::default(),
+            oversampling,
+            cutoff_hz: SmoothedParam::new(
+                cutoff_hz,
+                Default::default(),
+                cx.stream_info.sample_rate,
+            ),
+            gain: SmoothedParamBuffer::new(
+                self.volume.amp_clamped(DEFAULT_AMP_EPSILON),
+                Default::default(),
+                cx.stream_info,
+            ),
+            enable_declicker: Declicker::from_enabled(self.enabled),
+            sample_rate_recip,
+        };
+        processor.update_sample_rate(sample_rate_recip);
+        processor.set_cutoff(cutoff_hz);
+        processor
+    }
+}
+
+struct Processor {
+    ladder_l: Ladder,
+    ladder_r: Ladder,
+    /// The one-pole coefficient shared by every stage
+    g: f32,
+    resonance: f32,
+    drive: f32,
+    bass_compensation: f32,
+    /// Lowpass coefficients used before decimating back to the stream rate
+    anti_alias: SvfCoeffs,
+    oversampling: u32,
+    cutoff_hz: SmoothedParam,
+    gain: SmoothedParamBuffer,
+    enable_declicker: Declicker,
+    sample_rate_recip: f32,
+}
+
+impl Processor {
+    #[inline]
+    fn set_cutoff(&mut self, cutoff_hz: f32) {
+        let oversampled_recip = self.sample_rate_recip / self.oversampling as f32;
+        self.g = 1.0 - (-2.0 * PI * cutoff_hz * oversampled_recip).exp();
+    }
+
+    fn update_sample_rate(&mut self, sample_rate_recip: f32) {
+        self.sample_rate_recip = sample_rate_recip;
+
+        // cut just below the original nyquist frequency
+        let oversampled_recip = sample_rate_recip / self.oversampling as f32;
+        self.anti_alias =
+            SvfCoeffs::new(0.45 / sample_rate_recip, FRAC_1_SQRT_2, oversampled_recip);
+    }
+
+    fn reset(&mut self) {
+        self.ladder_l.reset();
+        self.ladder_r.reset();
+    }
+
+    #[inline]
+    fn process_frame(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let params = LadderParams {
+            g: self.g,
+            feedback: 4.0 * self.resonance,
+            drive: self.drive,
+            bass_compensation: self.bass_compensation,
+        };
+
+        (
+            self.ladder_l
+                .process(l, &params, &self.anti_alias, self.oversampling),
+            self.ladder_r
+                .process(r, &params, &self.anti_alias, self.oversampling),
+        )
+    }
+}
+
+impl AudioNodeProcessor for Processor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<LadderFilterNode>() {
+            match patch {
+                LadderFilterNodePatch::CutoffHz(cutoff) => {
+                    self.cutoff_hz.set_value(cutoff.clamp(20.0, 20_000.0));
+                }
+                LadderFilterNodePatch::Resonance(resonance) => {
+                    self.resonance = resonance.clamp(0.0, MAX_RESONANCE);
+                }
+                LadderFilterNodePatch::Drive(drive) => {
+                    self.drive = drive.clamp(0.1, MAX_DRIVE);
+                }
+                LadderFilterNodePatch::BassCompensation(bass_compensation) => {
+                    self.bass_compensation = bass_compensation.clamp(0.0, 1.0);
+                }
+                LadderFilterNodePatch::Volume(volume) => {
+                    self.gain.set_value(volume.amp_clamped(DEFAULT_AMP_EPSILON));
+                }
+                LadderFilterNodePatch::Enabled(enabled) => {
+                    self.enable_declicker
+                        .fade_to_enabled(enabled, proc_info.declick_values);
+                }
+            }
+        }
+
+        let upmix = is_upmixing(2, proc_info.in_connected_mask);
+        let inputs = stereo_inputs(buffers.inputs, upmix);
+        let active_channels = if upmix { 1 } else { 2 };
+
+        if self.enable_declicker.disabled() {
+            return bypass(buffers.inputs, buffers.outputs, proc_info.frames, upmix);
+        }
+
+        let gain_is_silent = !self.gain.is_smoothing() && self.gain.target_value() < 0.00001;
+
+        // A self-oscillating filter keeps ringing without any input, so only
+        // treat the input as silent when the resonance is below that point.
+        let inputs_silent = proc_info
+            .in_silence_mask
+            .all_channels_silent(active_channels)
+            && self.resonance < 1.0;
+
+        if inputs_silent || gain_is_silent {
+            self.cutoff_hz.reset();
+            self.gain.reset();
+            self.reset();
+            self.set_cutoff(self.cutoff_hz.target_value());
+            self.enable_declicker.reset_to_target();
+
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let in1 = &inputs[0][..proc_info.frames];
+        let in2 = &inputs[1][..proc_info.frames];
+        let (out1, out2) = buffers.outputs.split_first_mut().unwrap();
+        let out1 = &mut out1[..proc_info.frames];
+        let out2 = &mut out2[0][..proc_info.frames];
+
+        if self.cutoff_hz.is_smoothing() {
+            for i in 0..proc_info.frames {
+                let cutoff_hz = self.cutoff_hz.next_smoothed();
+
+                // only recalculate the coefficients every 16 frames
+                if i & (16 - 1) == 0 {
+                    self.set_cutoff(cutoff_hz);
+                }
+
+                (out1[i], out2[i]) = self.process_frame(in1[i], in2[i]);
+            }
+
+            self.cutoff_hz.settle();
+        } else {
+            self.set_cutoff(self.cutoff_hz.target_value());
+
+            for i in 0..proc_info.frames {
+                (out1[i], out2[i]) = self.process_frame(in1[i], in2[i]);
+            }
+        }
+
+        let gain = &self.gain.get_buffer(proc_info.frames).0[..proc_info.frames];
+        for i in 0..proc_info.frames {
+            out1[i] *= gain[i];
+            out2[i] *= gain[i];
+        }
+
+        self.enable_declicker.process_crossfade(
+            &inputs,
+            buffers.outputs,
+            proc_info.frames,
+            proc_info.declick_values,
+            FadeType::EqualPower3dB,
+        );
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.update_sample_rate(stream_info.sample_rate_recip as f32);
+
+        self.cutoff_hz.update_sample_rate(stream_info.sample_rate);
+        self.gain.update_stream(stream_info);
+
+        self.set_cutoff(self.cutoff_hz.target_value());
+    }
+}
+
+struct LadderParams {
+    g: f32,
+    feedback: f32,
+    drive: f32,
+    bass_compensation: f32,
+}
+
+/// The state of a single channel of the ladder filter
+#[derive(Default)]
+struct Ladder {
+    stages: [f32; 4],
+    prev_input: f32,
+    anti_alias: [Svf; 2],
+}
+
+impl Ladder {
+    fn reset(&mut self) {
+        self.stages = [0.0; 4];
+        self.prev_input = 0.0;
+        for filter in self.anti_alias.iter_mut() {
+            filter.reset();
+        }
+    }
+
+    #[inline]
+    fn process(
+        &mut self,
+        input: f32,
+        params: &LadderParams,
+        anti_alias: &SvfCoeffs,
+        oversampling: u32,
+    ) -> f32 {
+        let mut output = 0.0;
+
+        for n in 1..=oversampling {
+            // linearly interpolate the input up to the oversampled rate
+            let t = n as f32 / oversampling as f32;
+            let x = (self.prev_input + (input - self.prev_input) * t) * params.drive;
+
+            // subtracting some of the input from the feedback restores the low end
+            let feedback = params.feedback * (self.stages[3] - params.bass_compensation * x);
+            let u = (x - feedback).tanh();
+
+            let [s0, s1, s2, s3] = &mut self.stages;
+            *s0 += params.g * (u - s0.tanh());
+            *s1 += params.g * (s0.tanh() - s1.tanh());
+            *s2 += params.g * (s1.tanh() - s2.tanh());
+            *s3 += params.g * (s2.tanh() - s3.tanh());
+
+            let [aa1, aa2] = &mut self.anti_alias;
+            output = aa2.process(
+                aa1.process(*s3, anti_alias, SvfMode::Lowpass),
+                anti_alias,
+                SvfMode::Lowpass,
+            );
+        }
+
+        self.prev_input = input;
+
+        // keep the level roughly constant as the drive changes
+        output / params.drive.sqrt()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(resonance: f32, input: impl Fn(usize) -> f32, samples: usize) -> Vec<f32> {
+        let sample_rate_recip = 1.0 / 48_000.0;
+        let oversampling = 4;
+        let params = LadderParams {
+            g: 1.0 - (-2.0 * PI * 1_000.0 * sample_rate_recip / oversampling as f32).exp(),
+            feedback: 4.0 * resonance,
+            drive: 1.0,
+            bass_compensation: 0.0,
+        };
+        let anti_alias = SvfCoeffs::new(
+            0.45 / sample_rate_recip,
+            FRAC_1_SQRT_2,
+            sample_rate_recip / oversampling as f32,
+        );
+
+        let mut ladder = Ladder::default();
+        (0..samples)
+            .map(|i| ladder.process(input(i), &params, &anti_alias, oversampling))
+            .collect()
+    }
+
+    #[test]
+    fn test_self_oscillation() {
+        let impulse = |i| if i == 0 { 1.0 } else { 0.0 };
+
+        // without much resonance an impulse dies away
+        let output = run(0.2, impulse, 48_000);
+        assert!(output[40_000..].iter().all(|s| s.abs() < 1e-4));
+
+        // with enough resonance the filter keeps ringing on its own
+        let output = run(1.2, impulse, 48_000);
+        let peak = output[40_000..].iter().fold(0.0f32, |a, s| a.max(s.abs()));
+        assert!(peak > 0.1, "peak {peak}");
+    }
+}
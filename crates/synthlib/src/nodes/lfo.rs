@@ -0,0 +1,404 @@
+use std::f32::consts::PI;
+
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+};
+use wavetable::{WaveTableSampler, WaveTables, WaveType};
+
+use crate::{rng::Rng, tempo::NoteValue};
+
+const TABLE_SIZE: usize = 64;
+
+/// A low frequency oscillator that outputs a control signal for modulating other
+/// nodes. The rate can be set in hertz or synced to a note value at a tempo.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct LfoNode {
+    pub shape: LfoShape,
+    /// The rate in hertz, used when `tempo_sync` is disabled
+    pub rate_hz: f32,
+    /// When enabled one cycle lasts for `division` at `bpm`
+    pub tempo_sync: bool,
+    pub bpm: f32,
+    pub division: NoteValue,
+    /// The phase offset in cycles, in the range `[0.0, 1.0)`
+    pub phase_offset: f32,
+    pub polarity: Polarity,
+}
+
+impl Default for LfoNode {
+    fn default() -> Self {
+        Self {
+            shape: LfoShape::default(),
+            rate_hz: 1.0,
+            tempo_sync: false,
+            bpm: 120.0,
+            division: NoteValue::QUARTER,
+            phase_offset: 0.0,
+            polarity: Polarity::default(),
+        }
+    }
+}
+
+impl LfoNode {
+    /// The frequency of the LFO in hertz, taking tempo sync into account
+    pub fn frequency(&self) -> f32 {
+        if self.tempo_sync {
+            let seconds = self.division.seconds(self.bpm);
+            if seconds > 0.0 {
+                (1.0 / seconds) as f32
+            } else {
+                0.0
+            }
+        } else {
+            self.rate_hz.max(0.0)
+        }
+    }
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    /// Glides smoothly to a new random value every cycle
+    SmoothRandom,
+    /// Jumps to a new random value every cycle
+    SampleAndHold,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Polarity {
+    /// Output in the range `[-1.0, 1.0]`
+    #[default]
+    Bipolar,
+    /// Output in the range `[0.0, 1.0]`
+    Unipolar,
+}
+
+/// Events that can be sent to an [LfoNode] with `NodeEventType::custom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoEvent {
+    /// Restarts the cycle from the phase offset
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LfoConfig {
+    /// The seed for the random shapes
+    pub seed: u32,
+}
+
+impl Default for LfoConfig {
+    fn default() -> Self {
+        Self { seed: 1 }
+    }
+}
+
+impl AudioNode for LfoNode {
+    type Configuration = LfoConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("lfo")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::ZERO,
+                num_outputs: ChannelCount::MONO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        LfoProcessor::new(self, configuration.seed, cx.stream_info.sample_rate.into())
+    }
+}
+
+struct LfoProcessor {
+    params: LfoNode,
+    frequency: f32,
+    tables: WaveTables<TABLE_SIZE>,
+    /// Tracks the phase of the LFO, and samples the tables for the periodic shapes
+    sampler: WaveTableSampler,
+    rng: Rng,
+    /// The random values at the start and end of the current cycle
+    previous: f32,
+    next: f32,
+}
+
+impl LfoProcessor {
+    fn new(params: &LfoNode, seed: u32, sample_rate: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut processor = Self {
+            params: *params,
+            frequency: params.frequency(),
+            tables: WaveTables::default(),
+            sampler: WaveTableSampler {
+                sample_rate,
+                ..Default::default()
+            },
+            previous: rng.next_bipolar(),
+            next: rng.next_bipolar(),
+            rng,
+        };
+        processor.reset_phase();
+        processor
+    }
+
+    fn apply_patch(&mut self, patch: LfoNodePatch) {
+        if let LfoNodePatch::PhaseOffset(offset) = patch {
+            // shift the current phase rather than restarting the cycle
+            let delta = offset - self.params.phase_offset;
+            self.sampler.index =
+                (self.sampler.index + delta * TABLE_SIZE as f32).rem_euclid(TABLE_SIZE as f32);
+        }
+
+        self.params.apply(patch);
+        self.frequency = self.params.frequency();
+    }
+
+    fn reset_phase(&mut self) {
+        self.sampler.index = self.params.phase_offset.rem_euclid(1.0) * TABLE_SIZE as f32;
+    }
+
+    #[inline]
+    fn next_sample(&mut self) -> f32 {
+        let phase = self.sampler.index / TABLE_SIZE as f32;
+
+        let wave_type = match self.params.shape {
+            LfoShape::Sine => Some(WaveType::Sine),
+            LfoShape::Triangle => Some(WaveType::Triangle),
+            LfoShape::Saw => Some(WaveType::Saw),
+            LfoShape::Square => Some(WaveType::Square),
+            LfoShape::SmoothRandom | LfoShape::SampleAndHold => None,
+        };
+
+        let value = match wave_type {
+            Some(wave_type) => {
+                let value = self
+                    .sampler
+                    .sample(self.frequency, self.tables.get(wave_type));
+
+                // the square table is unipolar, unlike the others
+                if wave_type == WaveType::Square {
+                    value * 2.0 - 1.0
+                } else {
+                    value
+                }
+            }
+            None => {
+                let value = if self.params.shape == LfoShape::SampleAndHold {
+                    self.previous
+                } else {
+                    // cosine interpolation between the random values
+                    let t = 0.5 - 0.5 * (phase * PI).cos();
+                    self.previous + (self.next - self.previous) * t
+                };
+
+                // advance the phase using any table
+                self.sampler
+                    .sample(self.frequency, self.tables.get(WaveType::Saw));
+
+                value
+            }
+        };
+
+        if self.sampler.index / (TABLE_SIZE as f32) < phase {
+            // the phase has wrapped, so start a new random cycle
+            self.previous = self.next;
+            self.next = self.rng.next_bipolar();
+        }
+
+        match self.params.polarity {
+            Polarity::Bipolar => value,
+            Polarity::Unipolar => 0.5 * value + 0.5,
+        }
+    }
+}
+
+impl AudioNodeProcessor for LfoProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for event in events.drain() {
+            if let Some(patch) = LfoNode::patch_event(&event) {
+                self.apply_patch(patch);
+            } else if let Some(LfoEvent::Reset) = event.downcast_ref::<LfoEvent>() {
+                self.reset_phase();
+            }
+        }
+
+        for s in buffers.outputs[0][..proc_info.frames].iter_mut() {
+            *s = self.next_sample();
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.sampler.sample_rate = stream_info.sample_rate.into();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// One cycle of the LFO at 1 Hz lasts one sample per entry of the table
+    const SAMPLE_RATE: u32 = TABLE_SIZE as u32;
+
+    fn render(processor: &mut LfoProcessor, samples: usize) -> Vec<f32> {
+        (0..samples).map(|_| processor.next_sample()).collect()
+    }
+
+    fn lfo(shape: LfoShape, polarity: Polarity) -> LfoNode {
+        LfoNode {
+            shape,
+            polarity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tempo_synced_frequency() {
+        let lfo = LfoNode {
+            tempo_sync: true,
+            bpm: 120.0,
+            ..Default::default()
+        };
+        assert_eq!(lfo.frequency(), 2.0);
+
+        let lfo = LfoNode {
+            division: NoteValue::new(1, 1),
+            ..lfo
+        };
+        assert_eq!(lfo.frequency(), 0.5);
+
+        // the rate is ignored while synced, and can't go below 0 otherwise
+        let lfo = LfoNode {
+            rate_hz: -3.0,
+            ..lfo
+        };
+        assert_eq!(lfo.frequency(), 0.5);
+        assert_eq!(
+            LfoNode {
+                tempo_sync: false,
+                ..lfo
+            }
+            .frequency(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_polarity() {
+        let node = lfo(LfoShape::Square, Polarity::Bipolar);
+        let mut processor = LfoProcessor::new(&node, 1, SAMPLE_RATE);
+        let square = render(&mut processor, TABLE_SIZE);
+        assert!(square.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(square.contains(&-1.0) && square.contains(&1.0));
+
+        let node = lfo(LfoShape::Sine, Polarity::Unipolar);
+        let mut processor = LfoProcessor::new(&node, 1, SAMPLE_RATE);
+        let sine = render(&mut processor, TABLE_SIZE);
+        assert!(sine.iter().all(|s| (0.0..=1.0).contains(s)));
+        assert!((sine[0] - 0.5).abs() < 1e-6);
+        let max = sine.iter().copied().fold(f32::MIN, f32::max);
+        let min = sine.iter().copied().fold(f32::MAX, f32::min);
+        assert!(max > 0.99 && min < 0.01, "{min} to {max}");
+    }
+
+    #[test]
+    fn test_triangle() {
+        let node = lfo(LfoShape::Triangle, Polarity::Bipolar);
+        let mut processor = LfoProcessor::new(&node, 1, SAMPLE_RATE);
+        let triangle = render(&mut processor, TABLE_SIZE);
+
+        // one rise and one fall each cycle, with the peak halfway through
+        let peak = (0..TABLE_SIZE)
+            .max_by(|a, b| triangle[*a].total_cmp(&triangle[*b]))
+            .unwrap();
+        assert_eq!(peak, TABLE_SIZE / 2);
+        assert_eq!(triangle[0], -1.0);
+        assert_eq!(triangle[peak], 1.0);
+        assert!(triangle[..peak].is_sorted());
+        assert!(triangle[peak..].iter().rev().is_sorted());
+        assert!(triangle[TABLE_SIZE / 4].abs() < 1e-6);
+        assert!(triangle[TABLE_SIZE * 3 / 4].abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_phase_offset_patch() {
+        let node = lfo(LfoShape::Saw, Polarity::Bipolar);
+        let mut processor = LfoProcessor::new(&node, 1, SAMPLE_RATE);
+        render(&mut processor, 8);
+
+        // moving the offset shifts the phase from where it is now
+        processor.apply_patch(LfoNodePatch::PhaseOffset(0.25));
+        assert_eq!(processor.sampler.index, 8.0 + TABLE_SIZE as f32 * 0.25);
+
+        let mut offset = LfoProcessor::new(
+            &LfoNode {
+                phase_offset: 0.25,
+                ..node
+            },
+            1,
+            SAMPLE_RATE,
+        );
+        render(&mut offset, 8);
+        assert_eq!(render(&mut processor, 16), render(&mut offset, 16));
+
+        // a reset goes back to the offset rather than the start of the cycle
+        processor.reset_phase();
+        assert_eq!(processor.sampler.index, TABLE_SIZE as f32 * 0.25);
+    }
+
+    #[test]
+    fn test_random_shapes_are_reproducible() {
+        for shape in [LfoShape::SampleAndHold, LfoShape::SmoothRandom] {
+            let node = lfo(shape, Polarity::Bipolar);
+            let first = render(
+                &mut LfoProcessor::new(&node, 7, SAMPLE_RATE),
+                TABLE_SIZE * 4,
+            );
+            let again = render(
+                &mut LfoProcessor::new(&node, 7, SAMPLE_RATE),
+                TABLE_SIZE * 4,
+            );
+            let other = render(
+                &mut LfoProcessor::new(&node, 8, SAMPLE_RATE),
+                TABLE_SIZE * 4,
+            );
+
+            assert_eq!(first, again, "{shape:?}");
+            assert_ne!(first, other, "{shape:?}");
+            assert!(first.iter().all(|s| (-1.0..=1.0).contains(s)));
+        }
+
+        // sample and hold only changes at the start of each cycle
+        let node = lfo(LfoShape::SampleAndHold, Polarity::Bipolar);
+        let held = render(
+            &mut LfoProcessor::new(&node, 7, SAMPLE_RATE),
+            TABLE_SIZE * 2,
+        );
+        assert!(held[..TABLE_SIZE].iter().all(|s| *s == held[0]));
+        assert_ne!(held[TABLE_SIZE], held[0]);
+    }
+}
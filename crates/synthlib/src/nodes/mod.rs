@@ -1,3 +1,16 @@
+pub mod arpeggiator;
+pub mod convert;
+pub mod envelope;
 pub mod filter;
+pub mod hold;
+pub mod ladder;
+pub mod lfo;
+pub mod pan;
+pub mod poly;
+pub mod quantiser;
 pub mod sequencer;
+pub mod slew;
+pub mod svf;
+pub mod upmix;
+pub mod vca;
 pub mod wavetable;
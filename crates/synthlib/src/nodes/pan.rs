@@ -0,0 +1,174 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    dsp::pan_law::PanLaw,
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, EmptyConfig,
+        ProcBuffers, ProcInfo, ProcessStatus,
+    },
+    param::smoother::SmoothedParam,
+};
+
+/// The input channel for the pan control signal
+pub const PAN_INPUT: u32 = 1;
+
+/// Places a mono signal in the stereo field.
+///
+/// The first input is the audio and the second is an optional control input in
+/// the range `[-1.0, 1.0]` that is added to `pan`, for example from an
+/// [crate::nodes::lfo::LfoNode].
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct PanNode {
+    /// The pan position, where `-1.0` is fully left, `0.0` is center and `1.0` is
+    /// fully right
+    pub pan: f32,
+    pub pan_law: PanLaw,
+}
+
+impl AudioNode for PanNode {
+    type Configuration = EmptyConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("pan")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::STEREO,
+                num_outputs: ChannelCount::STEREO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        _configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        PanProcessor {
+            pan: SmoothedParam::new(
+                self.pan.clamp(-1.0, 1.0),
+                Default::default(),
+                cx.stream_info.sample_rate,
+            ),
+            pan_law: self.pan_law,
+        }
+    }
+}
+
+struct PanProcessor {
+    pan: SmoothedParam,
+    pan_law: PanLaw,
+}
+
+impl AudioNodeProcessor for PanProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<PanNode>() {
+            match patch {
+                PanNodePatch::Pan(pan) => self.pan.set_value(pan.clamp(-1.0, 1.0)),
+                PanNodePatch::PanLaw(pan_law) => self.pan_law = pan_law,
+            }
+        }
+
+        if proc_info.in_silence_mask.is_channel_silent(0) {
+            self.pan.reset();
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let frames = proc_info.frames;
+        let input = &buffers.inputs[0][..frames];
+        let cv = &buffers.inputs[PAN_INPUT as usize][..frames];
+        let cv_active = proc_info
+            .in_connected_mask
+            .is_channel_connected(PAN_INPUT as usize)
+            && !proc_info
+                .in_silence_mask
+                .is_channel_silent(PAN_INPUT as usize);
+
+        let (left, right) = buffers.outputs.split_first_mut().unwrap();
+        let left = &mut left[..frames];
+        let right = &mut right[0][..frames];
+
+        if cv_active || self.pan.is_smoothing() {
+            for i in 0..frames {
+                let cv = if cv_active { cv[i] } else { 0.0 };
+                let (gain_l, gain_r) = pan_gains(self.pan_law, self.pan.next_smoothed(), cv);
+
+                left[i] = input[i] * gain_l;
+                right[i] = input[i] * gain_r;
+            }
+
+            self.pan.settle();
+        } else {
+            let (gain_l, gain_r) = self.pan_law.compute_gains(self.pan.target_value());
+
+            for i in 0..frames {
+                left[i] = input[i] * gain_l;
+                right[i] = input[i] * gain_r;
+            }
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.pan.update_sample_rate(stream_info.sample_rate);
+    }
+}
+
+/// The left and right gains for a pan position with the control input added to it
+#[inline]
+fn pan_gains(pan_law: PanLaw, pan: f32, cv: f32) -> (f32, f32) {
+    pan_law.compute_gains((pan + cv).clamp(-1.0, 1.0))
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    use super::*;
+
+    fn assert_gains(gains: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (gains.0 - expected.0).abs() < 1e-6,
+            "{gains:?} != {expected:?}"
+        );
+        assert!(
+            (gains.1 - expected.1).abs() < 1e-6,
+            "{gains:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_constant_power() {
+        let law = PanLaw::EqualPower3dB;
+
+        assert_gains(pan_gains(law, -1.0, 0.0), (1.0, 0.0));
+        assert_gains(pan_gains(law, 0.0, 0.0), (FRAC_1_SQRT_2, FRAC_1_SQRT_2));
+        assert_gains(pan_gains(law, 1.0, 0.0), (0.0, 1.0));
+
+        for pan in [-0.75, -0.2, 0.4, 0.9] {
+            let (left, right) = pan_gains(law, pan, 0.0);
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_control_input() {
+        let law = PanLaw::EqualPower3dB;
+
+        assert_gains(pan_gains(law, 0.0, -1.0), pan_gains(law, -1.0, 0.0));
+        assert_gains(pan_gains(law, -0.5, 0.5), pan_gains(law, 0.0, 0.0));
+        assert_gains(pan_gains(law, 0.25, 0.5), pan_gains(law, 0.75, 0.0));
+        // the sum is clamped to the pan range
+        assert_gains(pan_gains(law, 1.0, 0.5), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,120 @@
+use firewheel::{
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    dsp::{pan_law::PanLaw, volume::Volume},
+    node::{AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext},
+};
+use wavetable::WaveType;
+
+use crate::nodes::envelope::Adsr;
+
+pub mod processor;
+mod voice;
+
+use processor::PolySynthProcessor;
+
+/// A polyphonic instrument that owns a fixed pool of voices, each with its own
+/// oscillator, envelope and lowpass filter. Notes are played by sending
+/// [PolySynthEvent]s to the node, and the voices are mixed down to stereo.
+///
+/// When every voice is busy a new note steals an existing voice according to
+/// the [VoiceStealing] policy, preferring voices that have already been released.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct PolySynthNode {
+    /// The wave type used by every voice's oscillator
+    pub wave_type: WaveType,
+    /// The envelope applied to each voice
+    pub envelope: Adsr,
+    /// The cutoff frequency of each voice's lowpass filter in the range `[20.0, 20_000.0]`
+    pub cutoff_hz: f32,
+    /// How voices are spread across the stereo field, where `0.0` places every voice
+    /// in the center and `1.0` spreads them from fully left to fully right.
+    pub stereo_spread: f32,
+    /// The policy used to pick a voice to steal when all voices are in use
+    pub voice_stealing: VoiceStealing,
+    /// The overall volume.
+    pub volume: Volume,
+}
+
+impl Default for PolySynthNode {
+    fn default() -> Self {
+        Self {
+            wave_type: WaveType::Saw,
+            envelope: Adsr::default(),
+            cutoff_hz: 5_000.0,
+            stereo_spread: 0.5,
+            voice_stealing: VoiceStealing::default(),
+            volume: Volume::default(),
+        }
+    }
+}
+
+/// Decides which voice is reused when a note is played and no voice is free
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoiceStealing {
+    /// Steal the voice that was triggered first
+    #[default]
+    Oldest,
+    /// Steal the voice with the lowest envelope level
+    Quietest,
+    /// Steal the voice playing the lowest frequency
+    Lowest,
+    /// Steal the voice playing the highest frequency
+    Highest,
+}
+
+/// Note events that can be sent to a [PolySynthNode] with
+/// `NodeEventType::custom`.
+///
+/// Each note is identified by a `key` chosen by the caller (for example a MIDI
+/// note number) so that the matching note off can find the voice playing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolySynthEvent {
+    NoteOn {
+        key: u32,
+        frequency: f32,
+        velocity: f32,
+    },
+    NoteOff {
+        key: u32,
+    },
+    AllNotesOff,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PolySynthConfig {
+    /// The number of voices that can play at once
+    pub voices: usize,
+    /// The pan law used when spreading voices across the stereo field
+    pub pan_law: PanLaw,
+}
+
+impl Default for PolySynthConfig {
+    fn default() -> Self {
+        Self {
+            voices: 8,
+            pan_law: PanLaw::default(),
+        }
+    }
+}
+
+impl AudioNode for PolySynthNode {
+    type Configuration = PolySynthConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("poly_synth")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::ZERO,
+                num_outputs: ChannelCount::STEREO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        PolySynthProcessor::new(self, configuration, cx.stream_info)
+    }
+}
@@ -0,0 +1,367 @@
+use firewheel::{
+    StreamInfo,
+    diff::Patch,
+    dsp::{pan_law::PanLaw, volume::DEFAULT_AMP_EPSILON},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{AudioNodeProcessor, ProcBuffers, ProcInfo, ProcessStatus},
+    param::smoother::{SmoothedParam, SmoothedParamBuffer},
+};
+use wavetable::{WaveTableSampler, WaveTables};
+
+use super::{PolySynthConfig, PolySynthEvent, PolySynthNode, PolySynthNodePatch, VoiceStealing};
+use crate::nodes::{envelope::Envelope, filter::OnePoleLPBiquad, poly::voice::Voice};
+
+pub struct PolySynthProcessor {
+    params: PolySynthNode,
+    pan_law: PanLaw,
+    tables: WaveTables<64>,
+    voices: Vec<Voice>,
+    /// Incremented for every note on, so voices can be ordered by age
+    note_counter: u64,
+    cutoff_hz: SmoothedParam,
+    gain: SmoothedParamBuffer,
+    sample_rate_recip: f32,
+}
+
+impl PolySynthProcessor {
+    pub fn new(params: &PolySynthNode, config: &PolySynthConfig, stream_info: &StreamInfo) -> Self {
+        let sample_rate: u32 = stream_info.sample_rate.into();
+        let sample_rate_recip = stream_info.sample_rate_recip as f32;
+        let cutoff_hz = params.cutoff_hz.clamp(20.0, 20_000.0);
+        let num_voices = config.voices.max(1);
+
+        let voices = (0..num_voices)
+            .map(|idx| {
+                Voice::new(
+                    WaveTableSampler {
+                        sample_rate,
+                        ..Default::default()
+                    },
+                    Envelope::new(params.envelope, sample_rate as f32),
+                    OnePoleLPBiquad::new(cutoff_hz, sample_rate_recip),
+                    config
+                        .pan_law
+                        .compute_gains(voice_pan(idx, num_voices, params.stereo_spread)),
+                )
+            })
+            .collect();
+
+        Self {
+            params: *params,
+            pan_law: config.pan_law,
+            tables: WaveTables::default(),
+            voices,
+            note_counter: 0,
+            cutoff_hz: SmoothedParam::new(cutoff_hz, Default::default(), stream_info.sample_rate),
+            gain: SmoothedParamBuffer::new(
+                params.volume.amp_clamped(DEFAULT_AMP_EPSILON),
+                Default::default(),
+                stream_info,
+            ),
+            sample_rate_recip,
+        }
+    }
+
+    fn handle_event(&mut self, event: &PolySynthEvent) {
+        match *event {
+            PolySynthEvent::NoteOn {
+                key,
+                frequency,
+                velocity,
+            } => {
+                let idx = self.find_voice(key);
+                self.note_counter += 1;
+                self.voices[idx].note_on(key, frequency, velocity, self.note_counter);
+            }
+            PolySynthEvent::NoteOff { key } => {
+                for voice in self.voices.iter_mut().filter(|v| v.key() == Some(key)) {
+                    voice.note_off();
+                }
+            }
+            PolySynthEvent::AllNotesOff => {
+                for voice in self.voices.iter_mut() {
+                    voice.note_off();
+                }
+            }
+        }
+    }
+
+    /// Finds the voice that should play the given key. A voice already holding the
+    /// key is retriggered, then a free voice is used. If there are no free voices one
+    /// is stolen using the current [VoiceStealing] policy, preferring released voices.
+    fn find_voice(&self, key: u32) -> usize {
+        if let Some(idx) = self.voices.iter().position(|v| v.key() == Some(key)) {
+            return idx;
+        }
+
+        if let Some(idx) = self.voices.iter().position(|v| !v.is_active()) {
+            return idx;
+        }
+
+        let any_released = self.voices.iter().any(Voice::is_released);
+        let candidates = self
+            .voices
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !any_released || v.is_released());
+
+        let stolen = match self.params.voice_stealing {
+            VoiceStealing::Oldest => candidates.min_by_key(|(_, v)| v.started_at()),
+            VoiceStealing::Quietest => {
+                candidates.min_by(|(_, a), (_, b)| a.level().total_cmp(&b.level()))
+            }
+            VoiceStealing::Lowest => {
+                candidates.min_by(|(_, a), (_, b)| a.frequency().total_cmp(&b.frequency()))
+            }
+            VoiceStealing::Highest => {
+                candidates.max_by(|(_, a), (_, b)| a.frequency().total_cmp(&b.frequency()))
+            }
+        };
+
+        stolen.map(|(idx, _)| idx).unwrap_or_default()
+    }
+
+    fn update_envelopes(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.set_adsr(self.params.envelope);
+        }
+    }
+
+    fn update_gains(&mut self) {
+        let num_voices = self.voices.len();
+        for (idx, voice) in self.voices.iter_mut().enumerate() {
+            voice.set_gains(self.pan_law.compute_gains(voice_pan(
+                idx,
+                num_voices,
+                self.params.stereo_spread,
+            )));
+        }
+    }
+
+    fn update_cutoff(&mut self, cutoff_hz: f32) {
+        if let Some((first, rest)) = self.voices.split_first_mut() {
+            first.set_cutoff(cutoff_hz, self.sample_rate_recip);
+            for voice in rest {
+                voice.copy_cutoff_from(first);
+            }
+        }
+    }
+}
+
+/// The pan position of a voice, spreading the voices evenly between left and right
+fn voice_pan(idx: usize, num_voices: usize, spread: f32) -> f32 {
+    if num_voices < 2 {
+        return 0.0;
+    }
+
+    let position = 2.0 * idx as f32 / (num_voices - 1) as f32 - 1.0;
+    position * spread.clamp(0.0, 1.0)
+}
+
+impl AudioNodeProcessor for PolySynthProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for event in events.drain() {
+            if let Some(patch) = PolySynthNode::patch_event(&event) {
+                match patch {
+                    PolySynthNodePatch::Envelope(patch) => {
+                        self.params.envelope.apply(patch);
+                        self.update_envelopes();
+                    }
+                    PolySynthNodePatch::CutoffHz(cutoff) => {
+                        self.params.cutoff_hz = cutoff;
+                        self.cutoff_hz.set_value(cutoff.clamp(20.0, 20_000.0));
+                    }
+                    PolySynthNodePatch::StereoSpread(spread) => {
+                        self.params.stereo_spread = spread;
+                        self.update_gains();
+                    }
+                    PolySynthNodePatch::Volume(volume) => {
+                        self.params.volume = volume;
+                        self.gain.set_value(volume.amp_clamped(DEFAULT_AMP_EPSILON));
+                    }
+                    PolySynthNodePatch::WaveType(wave_type) => {
+                        self.params.wave_type = wave_type;
+                    }
+                    PolySynthNodePatch::VoiceStealing(voice_stealing) => {
+                        self.params.voice_stealing = voice_stealing;
+                    }
+                }
+            } else if let Some(event) = event.downcast_ref::<PolySynthEvent>() {
+                self.handle_event(event);
+            }
+        }
+
+        if !self.voices.iter().any(Voice::is_active) {
+            // Nothing is playing, so there is nothing to smooth.
+            self.cutoff_hz.reset();
+            self.gain.reset();
+            self.update_cutoff(self.cutoff_hz.target_value());
+
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let (out1, out2) = buffers.outputs.split_first_mut().unwrap();
+        let out1 = &mut out1[..proc_info.frames];
+        let out2 = &mut out2[0][..proc_info.frames];
+
+        let cutoff_is_smoothing = self.cutoff_hz.is_smoothing();
+        if !cutoff_is_smoothing {
+            self.update_cutoff(self.cutoff_hz.target_value());
+        }
+
+        // leave some headroom as the voices are summed
+        let headroom = (self.voices.len() as f32).sqrt().recip();
+        let table = self.tables.get(self.params.wave_type);
+
+        for i in 0..proc_info.frames {
+            if cutoff_is_smoothing {
+                let cutoff_hz = self.cutoff_hz.next_smoothed();
+
+                // only recalculate the coefficients every 16 frames
+                if i & (16 - 1) == 0
+                    && let Some((first, rest)) = self.voices.split_first_mut()
+                {
+                    first.set_cutoff(cutoff_hz, self.sample_rate_recip);
+                    for voice in rest {
+                        voice.copy_cutoff_from(first);
+                    }
+                }
+            }
+
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for voice in self.voices.iter_mut().filter(|v| v.is_active()) {
+                let (l, r) = voice.process(table);
+                left += l;
+                right += r;
+            }
+
+            out1[i] = left * headroom;
+            out2[i] = right * headroom;
+        }
+
+        if cutoff_is_smoothing {
+            self.cutoff_hz.settle();
+        }
+
+        let gain = &self.gain.get_buffer(proc_info.frames).0[..proc_info.frames];
+        for i in 0..proc_info.frames {
+            out1[i] *= gain[i];
+            out2[i] *= gain[i];
+        }
+
+        ProcessStatus::outputs_not_silent()
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.sample_rate_recip = stream_info.sample_rate_recip as f32;
+
+        self.cutoff_hz.update_sample_rate(stream_info.sample_rate);
+        self.gain.update_stream(stream_info);
+
+        for voice in self.voices.iter_mut() {
+            voice.reset();
+            voice.set_sample_rate(stream_info.sample_rate.into());
+        }
+
+        self.update_cutoff(self.cutoff_hz.target_value());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn processor(voice_stealing: VoiceStealing) -> PolySynthProcessor {
+        PolySynthProcessor::new(
+            &PolySynthNode {
+                voice_stealing,
+                ..Default::default()
+            },
+            &PolySynthConfig {
+                voices: 3,
+                ..Default::default()
+            },
+            &StreamInfo::default(),
+        )
+    }
+
+    fn note_on(processor: &mut PolySynthProcessor, key: u32, frequency: f32) {
+        processor.handle_event(&PolySynthEvent::NoteOn {
+            key,
+            frequency,
+            velocity: 1.0,
+        });
+    }
+
+    #[test]
+    fn test_voice_stealing() {
+        let cases = [
+            (VoiceStealing::Oldest, 1),
+            (VoiceStealing::Lowest, 2),
+            (VoiceStealing::Highest, 3),
+        ];
+
+        for (policy, stolen_key) in cases {
+            let mut p = processor(policy);
+            note_on(&mut p, 1, 440.0);
+            note_on(&mut p, 2, 220.0);
+            note_on(&mut p, 3, 880.0);
+
+            let idx = p.find_voice(4);
+            assert_eq!(p.voices[idx].key(), Some(stolen_key), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn test_quietest_voice_is_stolen() {
+        let mut p = processor(VoiceStealing::Quietest);
+        for (key, velocity) in [(1, 1.0), (2, 0.25), (3, 0.5)] {
+            p.handle_event(&PolySynthEvent::NoteOn {
+                key,
+                frequency: 440.0,
+                velocity,
+            });
+        }
+
+        // run the envelopes past the attack so that the levels differ
+        let table = [0.0; 64];
+        for _ in 0..1_000 {
+            for voice in p.voices.iter_mut() {
+                voice.process(&table);
+            }
+        }
+
+        let idx = p.find_voice(4);
+        assert_eq!(p.voices[idx].key(), Some(2));
+
+        // a released voice is stolen before a quieter held one, and the quietest of
+        // the released voices is picked
+        p.handle_event(&PolySynthEvent::NoteOff { key: 1 });
+        p.handle_event(&PolySynthEvent::NoteOff { key: 3 });
+        assert_eq!(p.find_voice(4), 2);
+    }
+
+    #[test]
+    fn test_released_voices_are_stolen_first() {
+        let mut p = processor(VoiceStealing::Oldest);
+        note_on(&mut p, 1, 440.0);
+        note_on(&mut p, 2, 220.0);
+        note_on(&mut p, 3, 880.0);
+        p.handle_event(&PolySynthEvent::NoteOff { key: 3 });
+
+        // all voices are still active as voice 3 is releasing
+        let idx = p.find_voice(4);
+        assert_eq!(idx, 2);
+
+        // retriggering a held key reuses its voice
+        assert_eq!(p.find_voice(2), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use wavetable::WaveTableSampler;
+
+use crate::nodes::{
+    envelope::{Adsr, Envelope},
+    filter::OnePoleLPBiquad,
+};
+
+/// A single voice of a [super::PolySynthNode], made of an oscillator, an
+/// envelope and a lowpass filter.
+pub struct Voice {
+    /// The key currently held by this voice, or `None` once it has been released
+    key: Option<u32>,
+    frequency: f32,
+    /// The order in which this voice was triggered, used to find the oldest voice
+    started_at: u64,
+    gain_l: f32,
+    gain_r: f32,
+    sampler: WaveTableSampler,
+    envelope: Envelope,
+    filter: OnePoleLPBiquad,
+}
+
+impl Voice {
+    pub fn new(
+        sampler: WaveTableSampler,
+        envelope: Envelope,
+        filter: OnePoleLPBiquad,
+        gains: (f32, f32),
+    ) -> Self {
+        Self {
+            key: None,
+            frequency: 0.0,
+            started_at: 0,
+            gain_l: gains.0,
+            gain_r: gains.1,
+            sampler,
+            envelope,
+            filter,
+        }
+    }
+
+    pub fn note_on(&mut self, key: u32, frequency: f32, velocity: f32, started_at: u64) {
+        if !self.is_active() {
+            self.sampler.index = 0.0;
+            self.filter.reset();
+        }
+
+        self.key = Some(key);
+        self.frequency = frequency;
+        self.started_at = started_at;
+        self.envelope.gate_on(velocity);
+    }
+
+    pub fn note_off(&mut self) {
+        self.key = None;
+        self.envelope.gate_off();
+    }
+
+    /// Silences the voice immediately, without a release stage
+    pub fn reset(&mut self) {
+        self.key = None;
+        self.envelope.reset();
+        self.filter.reset();
+    }
+
+    pub fn key(&self) -> Option<u32> {
+        self.key
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn level(&self) -> f32 {
+        self.envelope.level()
+    }
+
+    /// A voice is active while its key is held or it is still releasing
+    pub fn is_active(&self) -> bool {
+        self.envelope.is_active()
+    }
+
+    pub fn is_released(&self) -> bool {
+        !self.envelope.is_gate_open()
+    }
+
+    pub fn set_gains(&mut self, gains: (f32, f32)) {
+        self.gain_l = gains.0;
+        self.gain_r = gains.1;
+    }
+
+    #[inline]
+    pub fn set_cutoff(&mut self, cutoff_hz: f32, sample_rate_recip: f32) {
+        self.filter.set_cutoff(cutoff_hz, sample_rate_recip);
+    }
+
+    #[inline]
+    pub fn copy_cutoff_from(&mut self, other: &Self) {
+        self.filter.copy_cutoff_from(&other.filter);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sampler.sample_rate = sample_rate;
+        self.envelope.set_sample_rate(sample_rate as f32);
+    }
+
+    pub fn set_adsr(&mut self, adsr: Adsr) {
+        self.envelope.set_adsr(adsr);
+    }
+
+    /// Renders the next sample of this voice, returning the left and right values
+    #[inline]
+    pub fn process<const R: usize>(&mut self, table: &[f32; R]) -> (f32, f32) {
+        let osc = self.sampler.sample(self.frequency, table);
+        let s = self.filter.process(osc) * self.envelope.next_sample();
+
+        (s * self.gain_l, s * self.gain_r)
+    }
+}
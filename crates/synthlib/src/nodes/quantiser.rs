@@ -0,0 +1,328 @@
+use firewheel::{
+    SilenceMask,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+};
+
+use crate::{
+    cv::frequency_to_voltage,
+    pitch::{DEFAULT_A4_HZ, midi_to_hz},
+    scale::Scale,
+    tuning::Tuning,
+};
+
+/// The output channel for the quantised pitch in volts per octave
+pub const PITCH_OUTPUT: u32 = 0;
+/// The output channel for the one sample trigger sent when the note changes
+pub const TRIGGER_OUTPUT: u32 = 1;
+
+/// The number of MIDI notes that can be quantised to
+const NUM_NOTES: usize = 128;
+/// How far, in volts, the note being played can move when the notes are worked out
+/// again and still count as the same note, which is half a semitone
+const SAME_NOTE_VOLTS: f32 = 1.0 / 24.0;
+
+/// Moves an incoming pitch in volts per octave (see [crate::cv]) to the nearest
+/// note of a scale. With an [crate::nodes::lfo::LfoNode] or noise in front of it,
+/// this plays melodies without a sequencer.
+///
+/// The outputs are the quantised pitch ([PITCH_OUTPUT]) and a trigger that fires
+/// when the note changes ([TRIGGER_OUTPUT]).
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct QuantiserNode {
+    pub notes: QuantiseTo,
+    /// How much closer, in cents, the input has to be to another note before the
+    /// output moves to it. This stops the output from flickering between two
+    /// notes when the input sits halfway between them.
+    pub hysteresis_cents: f32,
+    /// The reference pitch of A4 in hertz, used for everything but
+    /// [QuantiseTo::Tuning]
+    pub a4_hz: f32,
+}
+
+impl Default for QuantiserNode {
+    fn default() -> Self {
+        Self {
+            notes: QuantiseTo::Scale(Scale::default()),
+            hysteresis_cents: 0.0,
+            a4_hz: DEFAULT_A4_HZ,
+        }
+    }
+}
+
+/// The notes a [QuantiserNode] moves its input to
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub enum QuantiseTo {
+    Scale(Scale),
+    /// The notes picked out by the bits of `mask`, where bit 0 is `root` and each
+    /// bit after it is a semitone higher, repeating every octave. A mask without
+    /// any notes leaves the input as it is.
+    Mask {
+        root: u8,
+        mask: u16,
+    },
+    /// Every note of [QuantiserConfig::tuning]
+    Tuning,
+}
+
+impl QuantiseTo {
+    /// Whether a MIDI note is one of the notes, ignoring [QuantiseTo::Tuning]
+    pub fn contains(&self, note: u8) -> bool {
+        match self {
+            Self::Scale(scale) => scale.contains(note),
+            Self::Mask { root, mask } => {
+                let semitone = (note as i32 - *root as i32).rem_euclid(12);
+                mask & (1 << semitone) != 0
+            }
+            Self::Tuning => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QuantiserConfig {
+    /// The tuning for [QuantiseTo::Tuning], or equal temperament from A4 at
+    /// 440 Hz when `None`
+    pub tuning: Option<Tuning>,
+}
+
+impl AudioNode for QuantiserNode {
+    type Configuration = QuantiserConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("quantiser")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::MONO,
+                num_outputs: ChannelCount::STEREO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        _cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        let mut quantiser = Quantiser::new(configuration.tuning.clone().unwrap_or_default());
+        quantiser.set_params(self);
+        QuantiserProcessor {
+            params: *self,
+            quantiser,
+        }
+    }
+}
+
+/// Finds the nearest note to a pitch, keeping to the last note until the pitch is
+/// far enough away from it
+#[derive(Debug, Clone)]
+pub struct Quantiser {
+    tuning: Tuning,
+    /// The voltage of every note that can be played, from lowest to highest
+    notes: Vec<f32>,
+    /// The index of the note that was last played
+    current: Option<usize>,
+    /// The hysteresis in volts
+    hysteresis: f32,
+}
+
+impl Quantiser {
+    pub fn new(tuning: Tuning) -> Self {
+        Self {
+            tuning,
+            notes: Vec::with_capacity(NUM_NOTES),
+            current: None,
+            hysteresis: 0.0,
+        }
+    }
+
+    /// Works out the notes again. This doesn't allocate, so it can be called on the
+    /// audio thread.
+    pub fn set_params(&mut self, params: &QuantiserNode) {
+        let current = self.current.map(|i| self.notes[i]);
+
+        self.notes.clear();
+        for note in 0..NUM_NOTES as u8 {
+            let frequency = match params.notes {
+                QuantiseTo::Tuning => self.tuning.frequency(note as f32),
+                notes => notes
+                    .contains(note)
+                    .then(|| midi_to_hz(note as f32, params.a4_hz)),
+            };
+            if let Some(frequency) = frequency {
+                self.notes.push(frequency_to_voltage(frequency));
+            }
+        }
+        // tunings don't have to go up with every note
+        self.notes.sort_unstable_by(f32::total_cmp);
+
+        // the note being played carries on if it is still one of the notes, even
+        // if it has been retuned a little, so that it isn't triggered again
+        self.current = current.and_then(|voltage| {
+            self.nearest(voltage)
+                .filter(|i| (self.notes[*i] - voltage).abs() < SAME_NOTE_VOLTS)
+        });
+        self.hysteresis = params.hysteresis_cents.max(0.0) / 1200.0;
+    }
+
+    /// Quantises a pitch in volts per octave, returning the pitch of the note and
+    /// whether the note has changed
+    pub fn quantise(&mut self, voltage: f32) -> (f32, bool) {
+        let Some(nearest) = self.nearest(voltage) else {
+            return (voltage, false);
+        };
+        let distance = |i: usize| (voltage - self.notes[i]).abs();
+
+        let note = match self.current {
+            Some(current) if distance(current) - distance(nearest) <= self.hysteresis => current,
+            _ => nearest,
+        };
+        let changed = self.current != Some(note);
+        self.current = Some(note);
+
+        (self.notes[note], changed)
+    }
+
+    /// The index of the note closest to a voltage, or `None` without any notes
+    fn nearest(&self, voltage: f32) -> Option<usize> {
+        let distance = |i: usize| (voltage - self.notes[i]).abs();
+
+        // the nearest note is either side of where the voltage would go
+        let above = self.notes.partition_point(|note| *note < voltage);
+        match above {
+            _ if self.notes.is_empty() => None,
+            0 => Some(0),
+            i if i == self.notes.len() => Some(i - 1),
+            i if distance(i - 1) <= distance(i) => Some(i - 1),
+            i => Some(i),
+        }
+    }
+}
+
+struct QuantiserProcessor {
+    params: QuantiserNode,
+    quantiser: Quantiser,
+}
+
+impl AudioNodeProcessor for QuantiserProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        let mut changed = false;
+        for patch in events.drain_patches::<QuantiserNode>() {
+            self.params.apply(patch);
+            changed = true;
+        }
+        if changed {
+            self.quantiser.set_params(&self.params);
+        }
+
+        let frames = proc_info.frames;
+        let input = &buffers.inputs[0][..frames];
+        let (pitch_out, trigger_out) = buffers.outputs.split_at_mut(1);
+        let pitch_out = &mut pitch_out[0][..frames];
+        let trigger_out = &mut trigger_out[0][..frames];
+
+        for i in 0..frames {
+            let (pitch, changed) = self.quantiser.quantise(input[i]);
+            pitch_out[i] = pitch;
+            trigger_out[i] = if changed { 1.0 } else { 0.0 };
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cv::midi_to_voltage, scale::ScaleKind};
+
+    fn quantise(quantiser: &mut Quantiser, note: f32) -> (f32, bool) {
+        let (voltage, changed) = quantiser.quantise(midi_to_voltage(note));
+        ((voltage * 12.0 + 60.0).round(), changed)
+    }
+
+    #[test]
+    fn test_quantise_to_scale() {
+        let mut quantiser = Quantiser::new(Tuning::default());
+        quantiser.set_params(&QuantiserNode::default());
+
+        assert_eq!(quantise(&mut quantiser, 60.2), (60.0, true));
+        assert_eq!(quantise(&mut quantiser, 60.4), (60.0, false));
+        // C# is between C and D, and goes to the closer one
+        assert_eq!(quantise(&mut quantiser, 61.4), (62.0, true));
+        assert_eq!(quantise(&mut quantiser, 65.6), (65.0, true));
+
+        quantiser.set_params(&QuantiserNode {
+            notes: QuantiseTo::Mask {
+                root: 57,
+                mask: 0b1001,
+            },
+            ..Default::default()
+        });
+        // A and C in every octave
+        assert_eq!(quantise(&mut quantiser, 65.0), (69.0, true));
+        assert_eq!(quantise(&mut quantiser, 62.0), (60.0, true));
+
+        quantiser.set_params(&QuantiserNode {
+            notes: QuantiseTo::Scale(Scale::new(57, ScaleKind::MinorPentatonic)),
+            ..Default::default()
+        });
+        assert_eq!(quantise(&mut quantiser, 66.0), (67.0, true));
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut quantiser = Quantiser::new(Tuning::default());
+        quantiser.set_params(&QuantiserNode {
+            notes: QuantiseTo::Scale(Scale::new(60, ScaleKind::Chromatic)),
+            hysteresis_cents: 20.0,
+            ..Default::default()
+        });
+
+        assert_eq!(quantise(&mut quantiser, 60.0), (60.0, true));
+        assert_eq!(quantise(&mut quantiser, 60.55), (60.0, false));
+        assert_eq!(quantise(&mut quantiser, 60.65), (61.0, true));
+        assert_eq!(quantise(&mut quantiser, 60.45), (61.0, false));
+        assert_eq!(quantise(&mut quantiser, 60.35), (60.0, true));
+    }
+
+    #[test]
+    fn test_changing_params_keeps_the_note() {
+        let mut quantiser = Quantiser::new(Tuning::default());
+        let mut params = QuantiserNode {
+            hysteresis_cents: 20.0,
+            ..Default::default()
+        };
+        quantiser.set_params(&params);
+        assert_eq!(quantise(&mut quantiser, 60.0), (60.0, true));
+
+        // the same note, retuned or with a different hysteresis, isn't a new note
+        params.hysteresis_cents = 40.0;
+        quantiser.set_params(&params);
+        assert_eq!(quantise(&mut quantiser, 60.0), (60.0, false));
+        params.a4_hz = 442.0;
+        quantiser.set_params(&params);
+        assert_eq!(quantise(&mut quantiser, 60.0), (60.0, false));
+
+        // notes without it move to another one
+        params.notes = QuantiseTo::Mask {
+            root: 61,
+            mask: 0b1,
+        };
+        quantiser.set_params(&params);
+        assert_eq!(quantise(&mut quantiser, 60.0), (61.0, true));
+    }
+}
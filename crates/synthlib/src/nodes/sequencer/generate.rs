@@ -0,0 +1,301 @@
+use super::{EditTiming, SequenceStep, SequencerEvent, StepLength};
+use crate::{pitch::Pitch, rng::Rng, scale::Scale};
+
+/// Spreads `hits` as evenly as possible over `steps`, giving a Euclidean rhythm
+/// such as the tresillo for 3 hits over 8 steps. `rotation` moves every hit later
+/// by that many steps, wrapping around at the end.
+pub fn euclidean(hits: u32, steps: u32, rotation: u32) -> Vec<bool> {
+    let hits = hits.min(steps) as u64;
+    let steps = steps as u64;
+
+    (0..steps)
+        .map(|i| {
+            let i = (i + steps - rotation as u64 % steps) % steps;
+            (i * hits) % steps < hits
+        })
+        .collect()
+}
+
+/// The chances of moving between the degrees of a scale, which can be learnt from
+/// an existing melody
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkovChain {
+    /// The number of degrees, counting up from the root
+    degrees: usize,
+    /// The weight of moving from each degree to each other degree, one row per
+    /// degree
+    weights: Vec<f32>,
+}
+
+impl MarkovChain {
+    /// A chain over `degrees` degrees of a scale where every move is equally likely
+    pub fn new(degrees: usize) -> Self {
+        Self {
+            degrees,
+            weights: vec![1.0; degrees * degrees],
+        }
+    }
+
+    /// A chain with the moves between the degrees of a melody, so that the
+    /// generated lines move in the same way
+    pub fn learn(degrees: usize, melody: &[usize]) -> Self {
+        let mut chain = Self {
+            degrees,
+            weights: vec![0.0; degrees * degrees],
+        };
+
+        for pair in melody.windows(2) {
+            if pair[0] < degrees && pair[1] < degrees {
+                chain.weights[pair[0] * degrees + pair[1]] += 1.0;
+            }
+        }
+
+        chain
+    }
+
+    /// Sets the weight of moving from one degree to another
+    pub fn with_weight(mut self, from: usize, to: usize, weight: f32) -> Self {
+        if from < self.degrees && to < self.degrees {
+            self.weights[from * self.degrees + to] = weight.max(0.0);
+        }
+        self
+    }
+
+    pub fn degrees(&self) -> usize {
+        self.degrees
+    }
+
+    /// Picks the degree after `from`. A degree with nowhere to go moves to any
+    /// degree at random.
+    fn next(&self, from: usize, rng: &mut Rng) -> usize {
+        let row = &self.weights[from * self.degrees..(from + 1) * self.degrees];
+        let total: f32 = row.iter().sum();
+
+        if total <= 0.0 {
+            return rng.next_below(self.degrees as u32) as usize;
+        }
+
+        let mut choice = rng.next_f32() * total;
+        for (degree, weight) in row.iter().enumerate() {
+            if choice < *weight {
+                return degree;
+            }
+            choice -= weight;
+        }
+
+        // rounding can leave a tiny amount over, which belongs to the last move
+        row.iter().rposition(|w| *w > 0.0).unwrap_or_default()
+    }
+}
+
+/// Makes sequences rather than having every step written by hand. The random
+/// generators give a different sequence for each seed, so a playing sequence can
+/// be swapped for a new variation with [Generator::event].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    /// Plays `pitch` on the hits of a [euclidean] rhythm and rests in between
+    Euclidean {
+        hits: u32,
+        steps: u32,
+        rotation: u32,
+        pitch: Pitch,
+        length: StepLength,
+    },
+    /// Moves between the degrees of `scale` by the chances in `chain`, starting
+    /// from the degree `start`
+    Markov {
+        chain: MarkovChain,
+        scale: Scale,
+        start: usize,
+        steps: usize,
+        length: StepLength,
+    },
+    /// Moves up or down the scale by up to `max_move` degrees at a time, staying
+    /// between the degrees `low` and `high`
+    RandomWalk {
+        scale: Scale,
+        start: i32,
+        max_move: u32,
+        low: i32,
+        high: i32,
+        steps: usize,
+        length: StepLength,
+    },
+}
+
+impl Generator {
+    /// Makes a sequence. The same seed always makes the same sequence.
+    pub fn generate(&self, seed: u32) -> Vec<SequenceStep> {
+        let mut rng = Rng::new(seed);
+
+        match self {
+            Self::Euclidean {
+                hits,
+                steps,
+                rotation,
+                pitch,
+                length,
+            } => euclidean(*hits, *steps, *rotation)
+                .into_iter()
+                .map(|hit| {
+                    if hit {
+                        SequenceStep {
+                            pitch: Some(*pitch),
+                            length: *length,
+                            ..Default::default()
+                        }
+                    } else {
+                        SequenceStep::pause(*length)
+                    }
+                })
+                .collect(),
+            Self::Markov {
+                chain,
+                scale,
+                start,
+                steps,
+                length,
+            } => {
+                if chain.degrees() == 0 {
+                    return Vec::new();
+                }
+
+                let mut degree = (*start).min(chain.degrees() - 1);
+                (0..*steps)
+                    .map(|i| {
+                        if i > 0 {
+                            degree = chain.next(degree, &mut rng);
+                        }
+                        SequenceStep::midi(scale.note(degree as i32), *length)
+                    })
+                    .collect()
+            }
+            Self::RandomWalk {
+                scale,
+                start,
+                max_move,
+                low,
+                high,
+                steps,
+                length,
+            } => {
+                let (low, high) = (*low.min(high), *low.max(high));
+                let mut degree = (*start).clamp(low, high);
+                // a move can't usefully be wider than the range, and the number of
+                // possible moves has to fit in a u32
+                let max_move = (*max_move).min(high.abs_diff(low)).min(i32::MAX as u32);
+
+                (0..*steps)
+                    .map(|i| {
+                        if i > 0 {
+                            let step = rng.next_below(2 * max_move + 1) as i64 - max_move as i64;
+
+                            // bounce off the ends of the range rather than sticking
+                            let mut next = degree as i64 + step;
+                            if next < low as i64 || next > high as i64 {
+                                next -= 2 * step;
+                            }
+                            degree = next.clamp(low as i64, high as i64) as i32;
+                        }
+                        SequenceStep::midi(scale.note(degree), *length)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// An event that swaps the steps of a [super::SequencerNode] for a newly
+    /// generated sequence, for example with a new seed at the end of each loop
+    pub fn event(&self, seed: u32, timing: EditTiming) -> SequencerEvent {
+        SequencerEvent::Replace {
+            steps: self.generate(seed),
+            timing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scale::ScaleKind, tempo::NoteValue};
+
+    fn pattern(hits: &[bool]) -> String {
+        hits.iter()
+            .map(|hit| if *hit { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn test_euclidean() {
+        assert_eq!(pattern(&euclidean(3, 8, 0)), "x..x..x.");
+        assert_eq!(pattern(&euclidean(5, 8, 0)), "x.x.xx.x");
+        assert_eq!(pattern(&euclidean(3, 8, 2)), "x.x..x..");
+        assert_eq!(pattern(&euclidean(4, 4, 1)), "xxxx");
+        assert_eq!(pattern(&euclidean(0, 4, 0)), "....");
+        assert!(euclidean(3, 0, 1).is_empty());
+    }
+
+    #[test]
+    fn test_generators_are_reproducible() {
+        let scale = Scale::new(57, ScaleKind::MinorPentatonic);
+        let generators = [
+            Generator::Markov {
+                // up the scale and back down, always through the root
+                chain: MarkovChain::learn(5, &[0, 1, 2, 3, 4, 0, 4, 3, 2, 1, 0]),
+                scale,
+                start: 0,
+                steps: 32,
+                length: NoteValue::EIGHTH.into(),
+            },
+            Generator::RandomWalk {
+                scale,
+                start: 3,
+                max_move: 2,
+                low: 0,
+                high: 7,
+                steps: 32,
+                length: NoteValue::EIGHTH.into(),
+            },
+        ];
+
+        for generator in generators {
+            let steps = generator.generate(5);
+            assert_eq!(steps.len(), 32);
+            assert_eq!(steps, generator.generate(5));
+            assert_ne!(steps, generator.generate(6));
+
+            for step in steps {
+                let Some(Pitch::Midi(note)) = step.pitch() else {
+                    panic!("generated a step without a note");
+                };
+                assert!(scale.contains(note as u8));
+                assert!((57.0..=72.0).contains(&note));
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_walk_limits() {
+        let scale = Scale::new(60, ScaleKind::Major);
+        let walk = |max_move, low, high| Generator::RandomWalk {
+            scale,
+            start: 0,
+            max_move,
+            low,
+            high,
+            steps: 64,
+            length: NoteValue::EIGHTH.into(),
+        };
+
+        // moves wider than the range stay inside it
+        for step in walk(u32::MAX, 0, 7).generate(3) {
+            let Some(Pitch::Midi(note)) = step.pitch() else {
+                panic!("generated a step without a note");
+            };
+            assert!((60.0..=72.0).contains(&note));
+        }
+
+        // the widest range and moves don't overflow
+        assert_eq!(walk(u32::MAX, i32::MIN, i32::MAX).generate(3).len(), 64);
+    }
+}
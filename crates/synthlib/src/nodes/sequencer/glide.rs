@@ -0,0 +1,132 @@
+use firewheel::diff::{Diff, Patch};
+
+/// The shape of a glide between two notes
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlideCurve {
+    /// Moves through the frequencies at an even rate
+    Linear,
+    /// Moves through the pitches at an even rate, so that each semitone takes the
+    /// same time
+    #[default]
+    Exponential,
+}
+
+/// How the length of a glide is worked out from the glide time
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlideMode {
+    /// Every glide takes the glide time
+    #[default]
+    ConstantTime,
+    /// The glide time is the time taken to move an octave, so wider intervals
+    /// take longer
+    ConstantRate,
+}
+
+/// A frequency ramp from one note to the next
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(super) struct Glide {
+    from: f32,
+    elapsed: u64,
+    frames: u64,
+    curve: GlideCurve,
+}
+
+impl Glide {
+    /// Starts a glide from `from` to `to` hertz
+    pub fn start(
+        from: f32,
+        to: f32,
+        glide_ms: f32,
+        curve: GlideCurve,
+        mode: GlideMode,
+        sample_rate: u32,
+    ) -> Self {
+        let seconds = match mode {
+            GlideMode::ConstantTime => glide_ms / 1000.0,
+            GlideMode::ConstantRate => glide_ms / 1000.0 * (to / from).log2().abs(),
+        };
+
+        let frames = if from > 0.0 && to > 0.0 {
+            (seconds.max(0.0) * sample_rate as f32).round() as u64
+        } else {
+            0
+        };
+
+        Self {
+            from,
+            elapsed: 0,
+            frames,
+            curve,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.elapsed < self.frames
+    }
+
+    /// Fills `out` with the glide towards `to`, holding `to` once it is reached
+    pub fn fill(&mut self, out: &mut [f32], to: f32) {
+        for s in out.iter_mut() {
+            *s = if self.is_active() {
+                let t = self.elapsed as f32 / self.frames as f32;
+                self.elapsed += 1;
+
+                match self.curve {
+                    GlideCurve::Linear => self.from + (to - self.from) * t,
+                    GlideCurve::Exponential => self.from * (to / self.from).powf(t),
+                }
+            } else {
+                to
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::SQRT_2;
+
+    use super::*;
+
+    #[test]
+    fn test_glide() {
+        let mut out = [0.0; 6];
+
+        let mut glide = Glide::start(
+            100.0,
+            400.0,
+            4.0,
+            GlideCurve::Linear,
+            GlideMode::ConstantTime,
+            1_000,
+        );
+        glide.fill(&mut out, 400.0);
+        assert_eq!(out, [100.0, 175.0, 250.0, 325.0, 400.0, 400.0]);
+
+        let mut glide = Glide::start(
+            100.0,
+            400.0,
+            2.0,
+            GlideCurve::Exponential,
+            GlideMode::ConstantRate,
+            1_000,
+        );
+        glide.fill(&mut out, 400.0);
+        let expected = [100.0, 100.0 * SQRT_2, 200.0, 200.0 * SQRT_2, 400.0, 400.0];
+        for (out, expected) in out.iter().zip(expected) {
+            assert!((out - expected).abs() < 1e-3, "{out} != {expected}");
+        }
+
+        // there is nothing to glide from after a rest
+        let mut glide = Glide::start(
+            0.0,
+            400.0,
+            2.0,
+            GlideCurve::Linear,
+            GlideMode::ConstantTime,
+            1_000,
+        );
+        glide.fill(&mut out, 400.0);
+        assert_eq!(out, [400.0; 6]);
+    }
+}
@@ -0,0 +1,97 @@
+use firewheel::diff::{Diff, Patch};
+
+/// The swing that leaves the steps on a straight grid
+pub const STRAIGHT_SWING: f32 = 50.0;
+/// The most swing that can be applied, where the first step of each pair is three
+/// times as long as the second
+pub const MAX_SWING: f32 = 75.0;
+
+/// Decides whether a [super::SequenceStep] plays each time it comes around
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StepCondition {
+    #[default]
+    Always,
+    /// Plays on pass `offset` of every `n` passes through the loop, so
+    /// `Every { n: 4, offset: 3 }` plays on the fourth loop out of every four
+    Every { n: u32, offset: u32 },
+    /// Plays only on the first pass through the loop
+    First,
+    /// Plays on every pass through the loop apart from the first
+    NotFirst,
+    /// Plays only while [super::SequencerNode::fill] is on
+    Fill,
+    /// Plays only while [super::SequencerNode::fill] is off
+    NotFill,
+}
+
+impl StepCondition {
+    /// Whether the step plays on pass `loop_count` through the loop, counting from 0
+    pub fn is_met(&self, loop_count: u32, fill: bool) -> bool {
+        match *self {
+            Self::Always => true,
+            Self::Every { n, offset } => n == 0 || loop_count % n == offset % n,
+            Self::First => loop_count == 0,
+            Self::NotFirst => loop_count > 0,
+            Self::Fill => fill,
+            Self::NotFill => !fill,
+        }
+    }
+}
+
+/// Scales the length of a step for swing. Steps are swung in pairs, where the
+/// first step at an even `position` takes `swing` percent of the pair and the
+/// step after it takes the rest.
+pub(super) fn swing_ticks(ticks: u64, swing: f32, position: usize) -> u64 {
+    let swing = swing.clamp(STRAIGHT_SWING, MAX_SWING);
+
+    // the exact length is kept when there's no swing so that it can't drift
+    if swing == STRAIGHT_SWING {
+        return ticks;
+    }
+
+    let share = if position.is_multiple_of(2) {
+        swing
+    } else {
+        100.0 - swing
+    };
+    (ticks as f64 * share as f64 / STRAIGHT_SWING as f64).round() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_step_conditions() {
+        let plays = |condition: StepCondition, fill: bool| {
+            (0..6)
+                .map(|loop_count| condition.is_met(loop_count, fill))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            plays(StepCondition::Every { n: 3, offset: 1 }, false),
+            [false, true, false, false, true, false]
+        );
+        assert_eq!(
+            plays(StepCondition::First, false),
+            [true, false, false, false, false, false]
+        );
+        assert_eq!(plays(StepCondition::NotFirst, true)[..2], [false, true]);
+        assert_eq!(plays(StepCondition::Fill, true), [true; 6]);
+        assert_eq!(plays(StepCondition::NotFill, true), [false; 6]);
+        assert_eq!(
+            plays(StepCondition::Every { n: 0, offset: 0 }, false),
+            [true; 6]
+        );
+    }
+
+    #[test]
+    fn test_swing_ticks() {
+        assert_eq!(swing_ticks(1_000, STRAIGHT_SWING, 0), 1_000);
+        assert_eq!(swing_ticks(1_000, 60.0, 0), 1_200);
+        assert_eq!(swing_ticks(1_000, 60.0, 1), 800);
+        // swing is limited so that the second step of a pair never disappears
+        assert_eq!(swing_ticks(1_000, 100.0, 1), 500);
+    }
+}
@@ -0,0 +1,473 @@
+use firewheel::{
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    node::{AudioNode, AudioNodeInfo},
+};
+
+pub mod generate;
+pub mod glide;
+pub mod groove;
+pub mod multitrack;
+pub mod notation;
+pub mod processor;
+pub mod smf;
+pub mod transport;
+
+use glide::{GlideCurve, GlideMode};
+use groove::{STRAIGHT_SWING, StepCondition};
+use processor::SequencerProcessor;
+use transport::{PlayMode, SequencerState, Transport};
+
+use crate::{
+    pitch::{DEFAULT_A4_HZ, NoteParseError, Pitch},
+    tempo::{NoteValue, beats_to_seconds},
+    tuning::Tuning,
+};
+
+/// A Sequencer is a node that has no inputs and plays a sequence of "notes" and
+/// "pauses" on the output. It can be routed into another node such as a [WaveTableNode]
+/// to control the output frequency.
+///
+/// Step lengths in musical time are played at `bpm`, and MIDI notes are tuned
+/// relative to `a4_hz`. Both can be changed while the sequence is running. MIDI
+/// notes can instead be played in a microtonal [Tuning] (see
+/// [SequencerConfig::tuning] and [SequencerEvent::Retune]).
+///
+/// Steps marked as slides glide into the next note over `glide_ms`.
+///
+/// `swing` pushes back every other step, and `humanise` delays each note by a
+/// random amount. Steps can also be given their own delay, a chance of playing and
+/// a [StepCondition]. The random choices come from the seed in [SequencerConfig],
+/// so a sequence plays the same way every time it is rendered.
+///
+/// The steps from `loop_start` up to `loop_end` are played in the order set by
+/// `play_mode`. Playback is controlled by sending [Transport] commands, and the
+/// progress can be read back through the node's [SequencerState].
+///
+/// The outputs are, in order, the pitch of the note in volts per octave
+/// ([PITCH_OUTPUT], see [crate::cv]), a gate that is high while a note is held
+/// ([GATE_OUTPUT]), a one sample trigger at the start of each note
+/// ([TRIGGER_OUTPUT]), and the velocity ([VELOCITY_OUTPUT]) and accent
+/// ([ACCENT_OUTPUT]) of the current note. Pauses hold the last pitch with the gate
+/// low.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct SequencerNode {
+    /// The tempo in beats per minute, where a beat is a quarter note
+    pub bpm: f32,
+    /// The reference pitch of A4 in hertz
+    pub a4_hz: f32,
+    pub play_mode: PlayMode,
+    /// The index of the first step of the loop region
+    pub loop_start: u32,
+    /// The index after the last step of the loop region, or `None` to loop to the
+    /// end of the sequence
+    pub loop_end: Option<u32>,
+    /// The time taken to glide into the note after a slide step, or to glide an
+    /// octave with [GlideMode::ConstantRate]
+    pub glide_ms: f32,
+    pub glide_curve: GlideCurve,
+    pub glide_mode: GlideMode,
+    /// The share of each pair of steps given to the first step, in percent, where
+    /// `50.0` is straight and `66.7` is a triplet shuffle. Limited to `75.0`.
+    pub swing: f32,
+    /// The most each note is randomly delayed by, as a percentage of its step
+    pub humanise: f32,
+    /// Plays the steps with a [StepCondition::Fill] condition instead of those
+    /// with [StepCondition::NotFill]
+    pub fill: bool,
+}
+
+impl Default for SequencerNode {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            a4_hz: DEFAULT_A4_HZ,
+            play_mode: PlayMode::default(),
+            loop_start: 0,
+            loop_end: None,
+            glide_ms: 60.0,
+            glide_curve: GlideCurve::default(),
+            glide_mode: GlideMode::default(),
+            swing: STRAIGHT_SWING,
+            humanise: 0.0,
+            fill: false,
+        }
+    }
+}
+
+/// The lowest tempo the sequencer will run at
+const MIN_BPM: f32 = 1.0;
+
+pub const PITCH_OUTPUT: u32 = 0;
+pub const GATE_OUTPUT: u32 = 1;
+pub const TRIGGER_OUTPUT: u32 = 2;
+pub const VELOCITY_OUTPUT: u32 = 3;
+pub const ACCENT_OUTPUT: u32 = 4;
+pub(crate) const NUM_OUTPUTS: u32 = 5;
+
+/// How long a [SequenceStep] lasts
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub enum StepLength {
+    /// A fixed time in milliseconds, which ignores the tempo
+    Ms(u32),
+    /// A number of beats, where a beat is a quarter note
+    Beats(f32),
+    /// A musical note value such as an eighth or a dotted quarter
+    Note(NoteValue),
+}
+
+impl Default for StepLength {
+    fn default() -> Self {
+        Self::Note(NoteValue::QUARTER)
+    }
+}
+
+impl From<NoteValue> for StepLength {
+    fn from(value: NoteValue) -> Self {
+        Self::Note(value)
+    }
+}
+
+impl StepLength {
+    /// The length in seconds at the given tempo
+    pub fn seconds(&self, bpm: f32) -> f64 {
+        match self {
+            Self::Ms(ms) => *ms as f64 / 1000.0,
+            Self::Beats(beats) => beats_to_seconds(*beats as f64, bpm),
+            Self::Note(value) => value.seconds(bpm),
+        }
+    }
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct SequenceStep {
+    pitch: Option<Pitch>,
+    length: StepLength,
+    /// The velocity of the note in the range `[0.0, 1.0]`
+    velocity: f32,
+    accent: bool,
+    /// How much of the step the gate is held for, in percent
+    gate_percent: f32,
+    /// Holds the gate into the next note and glides to its pitch
+    slide: bool,
+    /// Holds the gate into the next note without retriggering it
+    tie: bool,
+    /// How far the note is pushed back from the start of the step, as a
+    /// percentage of the step
+    offset_percent: f32,
+    /// The chance of the note playing each time the step comes around, in percent
+    probability: f32,
+    condition: StepCondition,
+}
+
+impl Default for SequenceStep {
+    fn default() -> Self {
+        Self {
+            pitch: None,
+            length: StepLength::default(),
+            velocity: 1.0,
+            accent: false,
+            gate_percent: 100.0,
+            slide: false,
+            tie: false,
+            offset_percent: 0.0,
+            probability: 100.0,
+            condition: StepCondition::Always,
+        }
+    }
+}
+
+impl SequenceStep {
+    pub fn pause(length: impl Into<StepLength>) -> Self {
+        Self {
+            pitch: None,
+            length: length.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A note at a frequency in hertz
+    pub fn note(frequency: f32, length: impl Into<StepLength>) -> Self {
+        Self {
+            pitch: Some(Pitch::Hz(frequency)),
+            length: length.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A note from a MIDI note number, where 69 is A4
+    pub fn midi(note: u8, length: impl Into<StepLength>) -> Self {
+        Self {
+            pitch: Some(note.into()),
+            length: length.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A note from a name in scientific pitch notation such as `"A4"` or `"C#3"`
+    pub fn named(name: &str, length: impl Into<StepLength>) -> Result<Self, NoteParseError> {
+        Ok(Self {
+            pitch: Some(name.parse()?),
+            length: length.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Moves the note up or down by a number of semitones. Pauses are unchanged.
+    pub fn transposed(self, semitones: f32) -> Self {
+        Self {
+            pitch: self.pitch.map(|pitch| pitch.transposed(semitones)),
+            ..self
+        }
+    }
+
+    pub fn with_velocity(self, velocity: f32) -> Self {
+        Self {
+            velocity: velocity.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_accent(self, accent: bool) -> Self {
+        Self { accent, ..self }
+    }
+
+    /// Sets how much of the step the gate is held for, in percent. Shorter gates
+    /// give staccato notes, and `100.0` ties into the next note.
+    pub fn with_gate(self, gate_percent: f32) -> Self {
+        Self {
+            gate_percent: gate_percent.clamp(0.0, 100.0),
+            ..self
+        }
+    }
+
+    /// Holds the gate into the next note and glides to its pitch, like the slide
+    /// on a TB-303
+    pub fn with_slide(self, slide: bool) -> Self {
+        Self { slide, ..self }
+    }
+
+    /// Holds the gate into the next note so that it continues this one rather than
+    /// being retriggered
+    pub fn with_tie(self, tie: bool) -> Self {
+        Self { tie, ..self }
+    }
+
+    /// Pushes the note back from the start of the step by a percentage of the
+    /// step. Notes can only be played late, since a step can't start before the
+    /// one before it has finished.
+    pub fn with_offset(self, offset_percent: f32) -> Self {
+        Self {
+            offset_percent: offset_percent.clamp(0.0, 100.0),
+            ..self
+        }
+    }
+
+    /// Sets the chance of the note playing each time the step comes around, in
+    /// percent. A step that doesn't play is treated as a pause.
+    pub fn with_probability(self, probability: f32) -> Self {
+        Self {
+            probability: probability.clamp(0.0, 100.0),
+            ..self
+        }
+    }
+
+    pub fn with_condition(self, condition: StepCondition) -> Self {
+        Self { condition, ..self }
+    }
+
+    pub fn pitch(&self) -> Option<Pitch> {
+        self.pitch
+    }
+
+    /// The frequency of the note, using `a4_hz` as the reference for MIDI notes
+    pub fn frequency(&self, a4_hz: f32) -> Option<f32> {
+        self.pitch.map(|pitch| pitch.hz(a4_hz))
+    }
+
+    pub fn length(&self) -> StepLength {
+        self.length
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    pub fn accent(&self) -> bool {
+        self.accent
+    }
+
+    pub fn gate_percent(&self) -> f32 {
+        self.gate_percent
+    }
+
+    pub fn slide(&self) -> bool {
+        self.slide
+    }
+
+    pub fn tie(&self) -> bool {
+        self.tie
+    }
+
+    pub fn offset_percent(&self) -> f32 {
+        self.offset_percent
+    }
+
+    pub fn probability(&self) -> f32 {
+        self.probability
+    }
+
+    pub fn condition(&self) -> StepCondition {
+        self.condition
+    }
+
+    /// Whether the gate is held into the next step
+    fn is_legato(&self) -> bool {
+        self.pitch.is_some() && (self.slide || self.tie)
+    }
+}
+
+/// When an edit sent to a [SequencerNode] takes effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditTiming {
+    #[default]
+    Immediate,
+    /// Waits until the sequence loops back around to the first step
+    NextLoop,
+}
+
+/// A change to a single step of a playing sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepEdit {
+    /// Replaces the step at `index`. A change to the length of the step that is
+    /// playing takes effect the next time it plays.
+    Set { index: usize, step: SequenceStep },
+    /// Inserts a step before `index`, or at the end when `index` is the number of
+    /// steps
+    Insert { index: usize, step: SequenceStep },
+    /// Removes the step at `index`. The last step can't be removed.
+    Remove { index: usize },
+}
+
+/// Events that can be sent to a [SequencerNode] with `NodeEventType::custom` to
+/// control playback and change the sequence while it plays.
+///
+/// Edits never allocate on the audio thread, so steps can only be inserted while
+/// the sequence has spare room (see [SequencerConfig::capacity]). A replaced
+/// sequence is swapped into the event so that it is dropped off the audio thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SequencerEvent {
+    Edit {
+        edit: StepEdit,
+        timing: EditTiming,
+    },
+    /// Replaces every step. Give `steps` spare capacity if steps will be inserted
+    /// into it later. Any edits waiting for the next loop are applied after the
+    /// replacement.
+    Replace {
+        steps: Vec<SequenceStep>,
+        timing: EditTiming,
+    },
+    Transport(Transport),
+    /// Changes the tuning MIDI notes are played in, or goes back to equal
+    /// temperament from `a4_hz` with `None`. The previous tuning is swapped into
+    /// the event.
+    Retune(Option<Box<Tuning>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SequencerConfig {
+    pub sequences: Vec<SequenceStep>,
+    /// The number of steps there is room for, so that steps can be inserted while
+    /// the sequence plays
+    pub capacity: usize,
+    /// Whether the sequence starts playing as soon as the node is added
+    pub autoplay: bool,
+    /// The seed for the random play modes, humanise and step probabilities
+    pub seed: u32,
+    /// The tuning MIDI notes are played in, instead of equal temperament from
+    /// [SequencerNode::a4_hz]
+    pub tuning: Option<Tuning>,
+}
+
+impl SequencerConfig {
+    /// A sequence written in the text notation of [notation::parse_pattern]
+    pub fn from_pattern(text: &str) -> Result<Self, notation::NotationError> {
+        let sequences = notation::parse_pattern(text)?;
+        Ok(Self {
+            capacity: sequences.len().max(Self::default().capacity),
+            sequences,
+            ..Default::default()
+        })
+    }
+
+    /// Moves every note in the sequence up or down by a number of semitones
+    pub fn transpose(&mut self, semitones: f32) {
+        for step in self.sequences.iter_mut() {
+            *step = step.transposed(semitones);
+        }
+    }
+}
+
+impl Default for SequencerConfig {
+    fn default() -> Self {
+        Self {
+            sequences: vec![
+                SequenceStep::midi(69, NoteValue::HALF),
+                SequenceStep::pause(NoteValue::QUARTER),
+                SequenceStep::midi(57, NoteValue::QUARTER),
+                SequenceStep::pause(NoteValue::EIGHTH),
+                SequenceStep::midi(64, NoteValue::QUARTER),
+                SequenceStep::pause(NoteValue::EIGHTH),
+            ],
+            capacity: 64,
+            autoplay: true,
+            seed: 1,
+            tuning: None,
+        }
+    }
+}
+
+impl AudioNode for SequencerNode {
+    type Configuration = SequencerConfig;
+
+    fn info(&self, _configuration: &Self::Configuration) -> firewheel::node::AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("sine_node")
+            .custom_state(SequencerState::default())
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::ZERO,
+                num_outputs: ChannelCount::new(NUM_OUTPUTS).unwrap(),
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: firewheel::node::ConstructProcessorContext,
+    ) -> impl firewheel::node::AudioNodeProcessor {
+        let mut steps =
+            Vec::with_capacity(configuration.capacity.max(configuration.sequences.len()));
+        steps.extend_from_slice(&configuration.sequences);
+
+        let mut processor =
+            SequencerProcessor::new(steps, self.bpm, cx.stream_info.sample_rate.into());
+        processor.set_a4_hz(self.a4_hz);
+        processor.set_tuning(configuration.tuning.clone().map(Box::new));
+        processor.set_play_mode(self.play_mode);
+        processor.set_loop_region(self.loop_start, self.loop_end);
+        processor.set_glide(self.glide_ms, self.glide_curve, self.glide_mode);
+        processor.set_groove(self.swing, self.humanise, self.fill);
+        processor.set_seed(configuration.seed);
+        if let Some(state) = cx.custom_state::<SequencerState>() {
+            processor.set_shared_state(state.clone());
+        }
+        // start again now that everything the first step depends on has been set
+        processor.transport(if configuration.autoplay {
+            Transport::Reset
+        } else {
+            Transport::Stop
+        });
+        processor
+    }
+}
@@ -0,0 +1,680 @@
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+};
+
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount, MAX_CHANNELS},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+};
+
+use super::{
+    MIN_BPM, NUM_OUTPUTS, SequenceStep, StepLength,
+    groove::STRAIGHT_SWING,
+    processor::{SequencerProcessor, TICKS_PER_FRAME},
+    transport::{Transport, TransportState},
+};
+use crate::{pitch::DEFAULT_A4_HZ, tempo::beats_to_seconds, tuning::Tuning};
+
+/// The most tracks a [MultiTrackSequencerNode] can have
+pub const MAX_TRACKS: u32 = MAX_CHANNELS as u32 / NUM_OUTPUTS;
+
+/// The output channel of one of a track's outputs, such as [super::GATE_OUTPUT]
+#[inline]
+pub fn track_output(track: u32, output: u32) -> u32 {
+    track * NUM_OUTPUTS + output
+}
+
+/// A sequencer that plays several tracks from one clock, such as drums, bass and a
+/// lead line.
+///
+/// Each track has the same outputs as a [super::SequencerNode], one after the
+/// other, so the gate of the second track is `track_output(1, GATE_OUTPUT)`.
+/// Tracks loop on their own, so tracks of different lengths drift against each
+/// other to make polymeters.
+///
+/// The tracks play the [Pattern]s named in the song one after the other, and loop
+/// back to the start of the song at the end. A new pattern always starts on a bar
+/// line, with every track going back to its first step.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct MultiTrackSequencerNode {
+    /// The tempo in beats per minute, where a beat is a quarter note
+    pub bpm: f32,
+    /// The reference pitch of A4 in hertz
+    pub a4_hz: f32,
+    /// The swing of every track, see [super::SequencerNode::swing]
+    pub swing: f32,
+    /// The most each note is randomly delayed by, as a percentage of its step
+    pub humanise: f32,
+    /// Plays the steps with a [super::groove::StepCondition::Fill] condition
+    pub fill: bool,
+}
+
+impl Default for MultiTrackSequencerNode {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            a4_hz: DEFAULT_A4_HZ,
+            swing: STRAIGHT_SWING,
+            humanise: 0.0,
+            fill: false,
+        }
+    }
+}
+
+/// A named set of sequences with one for each track, which lasts a whole number of
+/// bars
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub name: String,
+    pub bars: u32,
+    /// The steps of each track. Tracks that are missing are silent.
+    pub tracks: Vec<Vec<SequenceStep>>,
+}
+
+impl Pattern {
+    pub fn new(name: impl Into<String>, bars: u32) -> Self {
+        Self {
+            name: name.into(),
+            bars,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Adds the steps of the next track
+    pub fn with_track(mut self, steps: Vec<SequenceStep>) -> Self {
+        self.tracks.push(steps);
+        self
+    }
+}
+
+/// A pattern in the song, which is played `repeats` times before moving on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongEntry {
+    pub pattern: String,
+    pub repeats: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SongError {
+    /// The song names a pattern that hasn't been added
+    UnknownPattern(String),
+}
+
+impl fmt::Display for SongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownPattern(name) => write!(f, "unknown pattern '{name}'"),
+        }
+    }
+}
+
+impl std::error::Error for SongError {}
+
+#[derive(Debug, Clone)]
+pub struct MultiTrackConfig {
+    /// The number of tracks, up to [MAX_TRACKS]
+    pub tracks: u32,
+    /// The length of a bar in beats, where a beat is a quarter note
+    pub beats_per_bar: f32,
+    pub patterns: Vec<Pattern>,
+    /// The patterns to play in order. Entries naming a pattern that doesn't exist
+    /// are skipped, and an empty song loops the first pattern.
+    pub song: Vec<SongEntry>,
+    /// Whether the song starts playing as soon as the node is added
+    pub autoplay: bool,
+    /// The seed for the random choices of the tracks
+    pub seed: u32,
+    /// The tuning MIDI notes are played in on every track, instead of equal
+    /// temperament from [MultiTrackSequencerNode::a4_hz]
+    pub tuning: Option<Tuning>,
+}
+
+impl Default for MultiTrackConfig {
+    fn default() -> Self {
+        Self {
+            tracks: 1,
+            beats_per_bar: 4.0,
+            patterns: Vec::new(),
+            song: Vec::new(),
+            autoplay: true,
+            seed: 1,
+            tuning: None,
+        }
+    }
+}
+
+impl MultiTrackConfig {
+    pub fn with_pattern(mut self, pattern: Pattern) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Adds a pattern to the end of the song, checking that it exists
+    pub fn chain(mut self, pattern: &str, repeats: u32) -> Result<Self, SongError> {
+        if !self.patterns.iter().any(|p| p.name == pattern) {
+            return Err(SongError::UnknownPattern(pattern.to_string()));
+        }
+
+        self.song.push(SongEntry {
+            pattern: pattern.to_string(),
+            repeats,
+        });
+        Ok(self)
+    }
+
+    fn num_tracks(&self) -> u32 {
+        self.tracks.clamp(1, MAX_TRACKS)
+    }
+}
+
+/// Events that can be sent to a [MultiTrackSequencerNode] with
+/// `NodeEventType::custom`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongEvent {
+    Transport(Transport),
+    /// Moves to an entry of the song at the next bar line
+    Jump {
+        entry: usize,
+    },
+}
+
+/// The position in the song of a [MultiTrackSequencerNode], which can be read
+/// from the main thread with `FirewheelContext::node_state::<SongState>(node_id)`.
+#[derive(Debug, Clone, Default)]
+pub struct SongState {
+    shared: Arc<SharedSongState>,
+}
+
+#[derive(Debug, Default)]
+struct SharedSongState {
+    playing: AtomicBool,
+    entry: AtomicU32,
+    bar: AtomicU32,
+}
+
+impl SongState {
+    pub fn is_playing(&self) -> bool {
+        self.shared.playing.load(Ordering::Relaxed)
+    }
+
+    /// The index of the song entry that is playing
+    pub fn entry(&self) -> usize {
+        self.shared.entry.load(Ordering::Relaxed) as usize
+    }
+
+    /// The bar within the song entry, counting from 0 over all of its repeats
+    pub fn bar(&self) -> u32 {
+        self.shared.bar.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, playing: bool, entry: usize, bar: u32) {
+        self.shared.playing.store(playing, Ordering::Relaxed);
+        self.shared.entry.store(entry as u32, Ordering::Relaxed);
+        self.shared.bar.store(bar, Ordering::Relaxed);
+    }
+}
+
+impl AudioNode for MultiTrackSequencerNode {
+    type Configuration = MultiTrackConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("multitrack_sequencer")
+            .custom_state(SongState::default())
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::ZERO,
+                num_outputs: ChannelCount::new(configuration.num_tracks() * NUM_OUTPUTS).unwrap(),
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        let mut processor =
+            SongProcessor::new(configuration, self.bpm, cx.stream_info.sample_rate.into());
+
+        for (i, track) in processor.tracks.iter_mut().enumerate() {
+            track.set_a4_hz(self.a4_hz);
+            track.set_tuning(configuration.tuning.clone().map(Box::new));
+            track.set_groove(self.swing, self.humanise, self.fill);
+            // each track gets its own seed so that they don't make the same choices
+            track.set_seed(configuration.seed.wrapping_add(i as u32));
+        }
+        if let Some(state) = cx.custom_state::<SongState>() {
+            processor.state = state.clone();
+        }
+
+        processor.transport(if configuration.autoplay {
+            Transport::Reset
+        } else {
+            Transport::Stop
+        });
+        processor
+    }
+}
+
+struct SongProcessor {
+    tracks: Vec<SequencerProcessor>,
+    /// The steps of each track for every pattern. The steps of the pattern that is
+    /// playing are swapped into the tracks, leaving the steps they had in their
+    /// place, so that changing pattern doesn't allocate.
+    patterns: Vec<Vec<Vec<SequenceStep>>>,
+    pattern_bars: Vec<u32>,
+    /// The index of the pattern and the number of repeats of each song entry
+    song: Vec<(usize, u32)>,
+    entry: usize,
+    /// The bar within the current song entry
+    bar: u32,
+    /// A song entry to jump to at the next bar line
+    jump: Option<usize>,
+    bpm: f32,
+    beats_per_bar: f32,
+    sample_rate: u32,
+    /// The number of frames of the current bar that have been played
+    elapsed: u64,
+    /// The length of the current bar in frames
+    bar_frames: u64,
+    /// The part of a frame, in ticks, that the previous bars ran over by
+    carry: u64,
+    transport: TransportState,
+    state: SongState,
+}
+
+impl SongProcessor {
+    fn new(configuration: &MultiTrackConfig, bpm: f32, sample_rate: u32) -> Self {
+        let num_tracks = configuration.num_tracks() as usize;
+        let beats_per_bar = configuration.beats_per_bar.max(f32::EPSILON);
+
+        let mut patterns = Vec::with_capacity(configuration.patterns.len().max(1));
+        let mut pattern_bars = Vec::with_capacity(patterns.capacity());
+        for pattern in &configuration.patterns {
+            let bars = pattern.bars.max(1);
+            let tracks = (0..num_tracks)
+                .map(|i| match pattern.tracks.get(i) {
+                    Some(steps) if !steps.is_empty() => steps.clone(),
+                    _ => silence(bars, beats_per_bar),
+                })
+                .collect();
+
+            patterns.push(tracks);
+            pattern_bars.push(bars);
+        }
+        if patterns.is_empty() {
+            patterns.push(vec![silence(1, beats_per_bar); num_tracks]);
+            pattern_bars.push(1);
+        }
+
+        let mut song: Vec<(usize, u32)> = configuration
+            .song
+            .iter()
+            .filter_map(|entry| {
+                let pattern = configuration
+                    .patterns
+                    .iter()
+                    .position(|p| p.name == entry.pattern)?;
+                Some((pattern, entry.repeats.max(1)))
+            })
+            .collect();
+        if song.is_empty() {
+            song.push((0, 1));
+        }
+
+        let mut processor = Self {
+            tracks: (0..num_tracks)
+                .map(|_| SequencerProcessor::new(Vec::new(), bpm, sample_rate))
+                .collect(),
+            patterns,
+            pattern_bars,
+            song,
+            entry: 0,
+            bar: 0,
+            jump: None,
+            bpm: bpm.max(MIN_BPM),
+            beats_per_bar,
+            sample_rate,
+            elapsed: 0,
+            bar_frames: 0,
+            carry: 0,
+            transport: TransportState::default(),
+            state: SongState::default(),
+        };
+
+        // the placeholder steps the tracks start with are kept in the place of the
+        // first pattern while it plays
+        let first = processor.song[0].0;
+        for (track, steps) in processor
+            .tracks
+            .iter_mut()
+            .zip(&mut processor.patterns[first])
+        {
+            track.swap_steps(steps);
+        }
+        processor.start_bar();
+        processor
+    }
+
+    fn is_playing(&self) -> bool {
+        self.transport == TransportState::Playing
+    }
+
+    fn transport(&mut self, command: Transport) {
+        match command {
+            Transport::Start => {
+                if self.transport == TransportState::Stopped {
+                    self.reset();
+                }
+                self.transport = TransportState::Playing;
+            }
+            Transport::Stop => {
+                self.transport = TransportState::Stopped;
+                self.reset();
+            }
+            Transport::Pause => {
+                if self.transport == TransportState::Playing {
+                    self.transport = TransportState::Paused;
+                }
+            }
+            Transport::Reset => self.reset(),
+        }
+
+        // the tracks only ever play together with the song
+        let command = match self.transport {
+            TransportState::Playing => Transport::Start,
+            TransportState::Paused => Transport::Pause,
+            TransportState::Stopped => Transport::Stop,
+        };
+        for track in self.tracks.iter_mut() {
+            track.transport(command);
+        }
+
+        self.report_state();
+    }
+
+    /// Goes back to the start of the song
+    fn reset(&mut self) {
+        self.jump = None;
+        self.carry = 0;
+        self.enter(0);
+    }
+
+    fn report_state(&self) {
+        self.state.set(self.is_playing(), self.entry, self.bar);
+    }
+
+    /// Starts playing a song entry from its first bar
+    fn enter(&mut self, entry: usize) {
+        let old = self.song[self.entry].0;
+        let new = self.song[entry].0;
+
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            // put back the steps of the old pattern before taking the new ones
+            track.swap_steps(&mut self.patterns[old][i]);
+            track.swap_steps(&mut self.patterns[new][i]);
+        }
+
+        self.entry = entry;
+        self.bar = 0;
+        self.start_bar();
+    }
+
+    fn start_bar(&mut self) {
+        let frames =
+            beats_to_seconds(self.beats_per_bar as f64, self.bpm) * self.sample_rate as f64;
+        let ticks = (frames * TICKS_PER_FRAME as f64).round() as u64 + self.carry;
+
+        self.bar_frames = (ticks / TICKS_PER_FRAME).max(1);
+        self.carry = ticks.saturating_sub(self.bar_frames * TICKS_PER_FRAME);
+        self.elapsed = 0;
+    }
+
+    /// Moves on to the next bar, changing pattern at the end of a song entry
+    fn next_bar(&mut self) {
+        let (pattern, repeats) = self.song[self.entry];
+        self.bar += 1;
+
+        if let Some(entry) = self.jump.take() {
+            self.enter(entry);
+        } else if self.bar >= self.pattern_bars[pattern] * repeats {
+            self.enter((self.entry + 1) % self.song.len());
+        } else {
+            self.start_bar();
+        }
+    }
+
+    /// Scales the part of the current bar that hasn't been played yet
+    fn stretch_remaining(&mut self, ratio: f64) {
+        let remaining = ((self.bar_frames - self.elapsed) as f64 * ratio).round() as u64;
+        self.bar_frames = self.elapsed + remaining.max(1);
+        self.carry = 0;
+    }
+
+    fn set_bpm(&mut self, bpm: f32) {
+        let bpm = bpm.max(MIN_BPM);
+        self.stretch_remaining(self.bpm as f64 / bpm as f64);
+        self.bpm = bpm;
+
+        for track in self.tracks.iter_mut() {
+            track.set_bpm(bpm);
+        }
+    }
+
+    fn handle_event(&mut self, event: &SongEvent) -> Result<(), &'static str> {
+        match *event {
+            SongEvent::Transport(command) => self.transport(command),
+            SongEvent::Jump { entry } => {
+                if entry >= self.song.len() {
+                    return Err("song entry out of range");
+                }
+                self.jump = Some(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Plays `frames` frames of every track, stopping at bar lines to change
+    /// pattern
+    fn render(&mut self, outputs: &mut [&mut [f32]], frames: usize) {
+        let mut current_sample_idx = 0;
+
+        while current_sample_idx < frames {
+            let samples =
+                ((self.bar_frames - self.elapsed) as usize).min(frames - current_sample_idx);
+
+            for (track, outputs) in self
+                .tracks
+                .iter_mut()
+                .zip(outputs.chunks_exact_mut(NUM_OUTPUTS as usize))
+            {
+                track.render(outputs, current_sample_idx, samples);
+            }
+
+            current_sample_idx += samples;
+            self.elapsed += samples as u64;
+
+            if self.elapsed >= self.bar_frames {
+                self.next_bar();
+            }
+        }
+    }
+}
+
+/// A pause lasting a number of bars, for tracks that a pattern doesn't use
+fn silence(bars: u32, beats_per_bar: f32) -> Vec<SequenceStep> {
+    vec![SequenceStep::pause(StepLength::Beats(
+        bars as f32 * beats_per_bar,
+    ))]
+}
+
+impl AudioNodeProcessor for SongProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for mut event in events.drain() {
+            if let Some(patch) = MultiTrackSequencerNode::patch_event(&event) {
+                match patch {
+                    MultiTrackSequencerNodePatch::Bpm(bpm) => self.set_bpm(bpm),
+                    MultiTrackSequencerNodePatch::A4Hz(a4_hz) => {
+                        self.tracks.iter_mut().for_each(|t| t.set_a4_hz(a4_hz));
+                    }
+                    MultiTrackSequencerNodePatch::Swing(swing) => {
+                        self.tracks.iter_mut().for_each(|t| t.set_swing(swing));
+                    }
+                    MultiTrackSequencerNodePatch::Humanise(humanise) => {
+                        self.tracks
+                            .iter_mut()
+                            .for_each(|t| t.set_humanise(humanise));
+                    }
+                    MultiTrackSequencerNodePatch::Fill(fill) => {
+                        self.tracks.iter_mut().for_each(|t| t.set_fill(fill));
+                    }
+                }
+            } else if let Some(event) = event.downcast_mut::<SongEvent>()
+                && let Err(e) = self.handle_event(event)
+            {
+                let _ = logger.try_error(e);
+            }
+        }
+
+        if !self.is_playing() {
+            self.report_state();
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        self.render(buffers.outputs, proc_info.frames);
+        self.report_state();
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        let sample_rate: u32 = stream_info.sample_rate.into();
+        if sample_rate != self.sample_rate {
+            self.stretch_remaining(sample_rate as f64 / self.sample_rate as f64);
+            self.sample_rate = sample_rate;
+        }
+
+        for track in self.tracks.iter_mut() {
+            track.set_sample_rate(sample_rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        nodes::sequencer::{PITCH_OUTPUT, TRIGGER_OUTPUT},
+        tempo::NoteValue,
+    };
+
+    /// Renders a number of frames and returns the outputs of every track
+    fn render(processor: &mut SongProcessor, frames: usize) -> Vec<Vec<f32>> {
+        let mut buffers = vec![vec![0.0; frames]; processor.tracks.len() * NUM_OUTPUTS as usize];
+        let mut outputs: Vec<&mut [f32]> = buffers.iter_mut().map(Vec::as_mut_slice).collect();
+        processor.render(&mut outputs, frames);
+        buffers
+    }
+
+    fn triggers(buffers: &[Vec<f32>], track: u32) -> Vec<usize> {
+        let trigger = &buffers[track_output(track, TRIGGER_OUTPUT) as usize];
+        (0..trigger.len()).filter(|i| trigger[*i] > 0.0).collect()
+    }
+
+    fn config() -> MultiTrackConfig {
+        MultiTrackConfig {
+            tracks: 2,
+            ..Default::default()
+        }
+        .with_pattern(
+            Pattern::new("verse", 1)
+                .with_track(vec![SequenceStep::midi(60, NoteValue::QUARTER)])
+                // three dotted quarters against the four quarters of the bar
+                .with_track(vec![SequenceStep::midi(36, NoteValue::QUARTER.dotted())]),
+        )
+        .with_pattern(
+            Pattern::new("chorus", 1).with_track(vec![SequenceStep::midi(72, NoteValue::HALF)]),
+        )
+    }
+
+    #[test]
+    fn test_song_chains_patterns() {
+        let config = config()
+            .chain("verse", 2)
+            .and_then(|config| config.chain("chorus", 1))
+            .unwrap();
+        assert_eq!(
+            config.clone().chain("bridge", 1).unwrap_err(),
+            SongError::UnknownPattern("bridge".to_string())
+        );
+
+        // a bar of 4/4 at 120 bpm lasts 2000 frames
+        let mut processor = SongProcessor::new(&config, 120.0, 1_000);
+        let buffers = render(&mut processor, 7_000);
+
+        let pitch = &buffers[track_output(0, PITCH_OUTPUT) as usize];
+        assert_eq!(pitch[3_999], note_voltage(60.0));
+        assert_eq!(pitch[4_000], note_voltage(72.0));
+        // the song loops back to the start
+        assert_eq!(pitch[6_000], note_voltage(60.0));
+
+        assert_eq!(
+            triggers(&buffers, 0),
+            [
+                0, 500, 1_000, 1_500, 2_000, 2_500, 3_000, 3_500, 4_000, 5_000, 6_000, 6_500
+            ]
+        );
+        // the second track carries on across the repeat, and is silent in the chorus
+        assert_eq!(
+            triggers(&buffers, 1),
+            [0, 750, 1_500, 2_250, 3_000, 3_750, 6_000, 6_750]
+        );
+    }
+
+    #[test]
+    fn test_jumps_wait_for_the_bar_line() {
+        let config = config()
+            .chain("verse", 4)
+            .and_then(|config| config.chain("chorus", 1))
+            .unwrap();
+        let mut processor = SongProcessor::new(&config, 120.0, 1_000);
+
+        render(&mut processor, 700);
+        processor
+            .handle_event(&SongEvent::Jump { entry: 1 })
+            .unwrap();
+        assert_eq!(
+            processor.handle_event(&SongEvent::Jump { entry: 2 }),
+            Err("song entry out of range")
+        );
+
+        let buffers = render(&mut processor, 1_400);
+        let pitch = &buffers[track_output(0, PITCH_OUTPUT) as usize];
+        assert_eq!(pitch[1_299], note_voltage(60.0));
+        assert_eq!(pitch[1_300], note_voltage(72.0));
+        assert_eq!(processor.entry, 1);
+    }
+
+    fn note_voltage(note: f32) -> f32 {
+        crate::cv::frequency_to_voltage(crate::pitch::midi_to_hz(note, DEFAULT_A4_HZ))
+    }
+}
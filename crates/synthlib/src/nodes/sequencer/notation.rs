@@ -0,0 +1,401 @@
+use std::fmt;
+
+use super::{SequenceStep, StepLength};
+use crate::{
+    pitch::{NoteParseError, parse_note_name},
+    tempo::NoteValue,
+};
+
+/// The most steps a group, or the whole sequence, can have once its repeats
+/// are played out. This stops a few nested repeats from asking for more memory
+/// than there is.
+pub const MAX_STEPS: usize = 65_536;
+
+/// Parses a sequence written as text, such as `"a4:8 . c5:16 e5:16 ~ [g4 b4]*2"`.
+///
+/// Steps are separated by spaces:
+/// - A note is a name in scientific pitch notation, such as `a4` or `c#5`,
+///   optionally followed by a length after a colon. Lengths are the fraction of a
+///   whole note, so `:8` is an eighth, and can be dotted (`:8.`) or made a
+///   triplet (`:8t`). A step without a length keeps the length of the step
+///   before, starting from a quarter note.
+/// - `.` is a rest, which can also be given a length, as in `.:16`.
+/// - `~` holds the note before for another step without retriggering it.
+/// - `[` and `]` group steps, and any step or group can be repeated by following
+///   it with `*` and a count, as in `[c4 e4]*4`. A sequence can't have more than
+///   [MAX_STEPS] steps.
+pub fn parse_pattern(text: &str) -> Result<Vec<SequenceStep>, NotationError> {
+    let mut parser = Parser {
+        length: NoteValue::QUARTER,
+        // the steps of each group that is open, with the column of its bracket
+        groups: vec![(Vec::new(), 0)],
+    };
+
+    for token in tokenize(text) {
+        parser.token(token)?;
+    }
+
+    let (steps, column) = parser.groups.pop().unwrap();
+    if !parser.groups.is_empty() {
+        return Err(NotationError::new(
+            column,
+            1,
+            NotationErrorKind::UnclosedGroup,
+        ));
+    }
+
+    Ok(steps)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationErrorKind {
+    InvalidNote(NoteParseError),
+    /// A length that isn't a power of two fraction of a whole note
+    InvalidLength(String),
+    /// A repeat count that isn't a whole number above 0, or that makes more than
+    /// [MAX_STEPS] steps
+    InvalidRepeat(String),
+    /// A step that makes the sequence longer than [MAX_STEPS] steps
+    TooManySteps,
+    /// A tie with no note before it to hold
+    TieWithoutNote,
+    /// A `[` without a matching `]`
+    UnclosedGroup,
+    /// A `]` without a matching `[`
+    UnopenedGroup,
+}
+
+/// An error in a sequence written as text, with the column it starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError {
+    /// The column of the start of the error, counting characters from 1
+    pub column: usize,
+    /// The number of characters the error covers
+    pub len: usize,
+    pub kind: NotationErrorKind,
+}
+
+impl NotationError {
+    fn new(column: usize, len: usize, kind: NotationErrorKind) -> Self {
+        Self {
+            column,
+            len: len.max(1),
+            kind,
+        }
+    }
+
+    /// Shows the text the error came from with the error underlined, for example
+    ///
+    /// ```text
+    /// a4:8 c5:7
+    ///        ^ invalid length '7'
+    /// ```
+    pub fn underline(&self, text: &str) -> String {
+        format!(
+            "{text}\n{}{} {}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.len),
+            self.kind
+        )
+    }
+}
+
+impl fmt::Display for NotationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNote(e) => write!(f, "{e}"),
+            Self::InvalidLength(length) => write!(f, "invalid length '{length}'"),
+            Self::InvalidRepeat(count) => write!(f, "invalid repeat count '{count}'"),
+            Self::TooManySteps => write!(f, "more than {MAX_STEPS} steps"),
+            Self::TieWithoutNote => write!(f, "tie without a note before it"),
+            Self::UnclosedGroup => write!(f, "'[' is never closed"),
+            Self::UnopenedGroup => write!(f, "']' without an opening '['"),
+        }
+    }
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.kind)
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+/// A word of the notation with the column it starts at
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// Splits the text at spaces, with brackets as words of their own. A repeat
+/// count stays attached to the `]` before it.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (column, (i, c)) in text.char_indices().enumerate() {
+        let column = column + 1;
+
+        if (c.is_whitespace() || c == '[' || c == ']')
+            && let Some((start_i, start_column)) = start.take()
+        {
+            tokens.push(Token {
+                text: &text[start_i..i],
+                column: start_column,
+            });
+        }
+
+        match c {
+            '[' => tokens.push(Token {
+                text: &text[i..i + 1],
+                column,
+            }),
+            ']' => start = Some((i, column)),
+            c if c.is_whitespace() => {}
+            _ => {
+                start.get_or_insert((i, column));
+            }
+        }
+    }
+
+    if let Some((start_i, start_column)) = start {
+        tokens.push(Token {
+            text: &text[start_i..],
+            column: start_column,
+        });
+    }
+
+    tokens
+}
+
+struct Parser {
+    /// The length of steps that don't give one
+    length: NoteValue,
+    groups: Vec<(Vec<SequenceStep>, usize)>,
+}
+
+impl Parser {
+    fn token(&mut self, token: Token) -> Result<(), NotationError> {
+        let column = token.column;
+
+        // the repeat count comes off the end first
+        let (body, count) = match token.text.split_once('*') {
+            Some((body, count)) => (body, Some((column + body.chars().count() + 1, count))),
+            None => (token.text, None),
+        };
+        // a bad or too large count is blamed on the count, and a plain step that
+        // overfills the sequence on the step itself
+        let repeat_error = || match count {
+            Some((count_column, count)) => NotationError::new(
+                count_column,
+                count.chars().count(),
+                NotationErrorKind::InvalidRepeat(count.to_string()),
+            ),
+            None => NotationError::new(
+                column,
+                token.text.chars().count(),
+                NotationErrorKind::TooManySteps,
+            ),
+        };
+        let repeats = match count {
+            Some((_, count)) => count
+                .parse::<usize>()
+                .ok()
+                .filter(|repeats| (1..=MAX_STEPS).contains(repeats))
+                .ok_or_else(repeat_error)?,
+            None => 1,
+        };
+
+        let steps = match body {
+            "[" => {
+                self.groups.push((Vec::new(), column));
+                return Ok(());
+            }
+            "]" => {
+                if self.groups.len() == 1 {
+                    return Err(NotationError::new(
+                        column,
+                        1,
+                        NotationErrorKind::UnopenedGroup,
+                    ));
+                }
+                self.groups.pop().unwrap().0
+            }
+            "~" => {
+                let steps = &mut self.groups.last_mut().unwrap().0;
+                let Some(last) = steps.last_mut().filter(|step| step.pitch.is_some()) else {
+                    return Err(NotationError::new(
+                        column,
+                        1,
+                        NotationErrorKind::TieWithoutNote,
+                    ));
+                };
+
+                *last = last.with_tie(true);
+                let held = last.with_tie(false);
+                vec![SequenceStep {
+                    length: self.length.into(),
+                    ..held
+                }]
+            }
+            body => vec![self.step(body, column)?],
+        };
+
+        let group = &mut self.groups.last_mut().unwrap().0;
+        let total = steps
+            .len()
+            .checked_mul(repeats)
+            .and_then(|len| len.checked_add(group.len()));
+        if total.is_none_or(|total| total > MAX_STEPS) {
+            return Err(repeat_error());
+        }
+        for _ in 0..repeats {
+            group.extend_from_slice(&steps);
+        }
+
+        Ok(())
+    }
+
+    /// Parses a note or rest with an optional length
+    fn step(&mut self, text: &str, column: usize) -> Result<SequenceStep, NotationError> {
+        let (name, length) = match text.split_once(':') {
+            Some((name, length)) => {
+                let length_column = column + name.chars().count() + 1;
+                self.length = parse_length(length).ok_or_else(|| {
+                    NotationError::new(
+                        length_column,
+                        length.chars().count(),
+                        NotationErrorKind::InvalidLength(length.to_string()),
+                    )
+                })?;
+                (name, self.length)
+            }
+            None => (text, self.length),
+        };
+
+        if name == "." {
+            return Ok(SequenceStep::pause(StepLength::Note(length)));
+        }
+
+        let note = parse_note_name(name).map_err(|e| {
+            NotationError::new(
+                column,
+                name.chars().count(),
+                NotationErrorKind::InvalidNote(e),
+            )
+        })?;
+        Ok(SequenceStep::midi(note, length))
+    }
+}
+
+/// Parses a length such as `8`, `4.` or `16t`
+fn parse_length(text: &str) -> Option<NoteValue> {
+    let (denominator, modifier) = match text.char_indices().last()? {
+        (i, '.' | 't') => text.split_at(i),
+        _ => (text, ""),
+    };
+
+    let denominator: u32 = denominator.parse().ok()?;
+    if !denominator.is_power_of_two() || denominator > 128 {
+        return None;
+    }
+
+    let value = NoteValue::new(1, denominator);
+    Some(match modifier {
+        "." => value.dotted(),
+        "t" => value.triplet(),
+        _ => value,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pitch::Pitch;
+
+    fn describe(steps: &[SequenceStep]) -> Vec<(Option<Pitch>, f64, bool)> {
+        steps
+            .iter()
+            .map(|step| {
+                let StepLength::Note(length) = step.length() else {
+                    panic!("expected a note length");
+                };
+                (step.pitch(), length.beats(), step.tie())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        let steps = parse_pattern("a4:8 . c5:16 e5 ~ [g4 b4:8.]*2 .:4t").unwrap();
+
+        assert_eq!(
+            describe(&steps),
+            [
+                (Some(69.into()), 0.5, false),
+                (None, 0.5, false),
+                (Some(72.into()), 0.25, false),
+                (Some(76.into()), 0.25, true),
+                (Some(76.into()), 0.25, false),
+                (Some(67.into()), 0.25, false),
+                (Some(71.into()), 0.75, false),
+                // a repeat plays the steps again exactly as they were written
+                (Some(67.into()), 0.25, false),
+                (Some(71.into()), 0.75, false),
+                (None, 2.0 / 3.0, false),
+            ]
+        );
+
+        assert!(parse_pattern("  ").unwrap().is_empty());
+        assert_eq!(parse_pattern("[[c4]*2 d4]*2").unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_notation_errors() {
+        let error = |text| parse_pattern(text).unwrap_err();
+
+        assert_eq!(
+            error("a4:8 c5:7"),
+            NotationError::new(9, 1, NotationErrorKind::InvalidLength("7".to_string()))
+        );
+        assert_eq!(
+            error("c4 h4"),
+            NotationError::new(
+                4,
+                2,
+                NotationErrorKind::InvalidNote(NoteParseError::InvalidLetter('h'))
+            )
+        );
+        assert_eq!(
+            error("c4 [d4]*0").kind,
+            NotationErrorKind::InvalidRepeat("0".to_string())
+        );
+        assert_eq!(
+            error("c4 [d4]*99999999999999999999").kind,
+            NotationErrorKind::InvalidRepeat("99999999999999999999".to_string())
+        );
+        // nested repeats can't multiply past the limit
+        assert_eq!(
+            error("[[c4]*100000]*100000"),
+            NotationError::new(7, 6, NotationErrorKind::InvalidRepeat("100000".to_string()))
+        );
+        assert_eq!(
+            error("[[c4 d4]*20000]*2"),
+            NotationError::new(17, 1, NotationErrorKind::InvalidRepeat("2".to_string()))
+        );
+        assert_eq!(
+            error("[c4*60000] [d4*60000]").kind,
+            NotationErrorKind::TooManySteps
+        );
+        assert_eq!(parse_pattern("[c4]*65536").unwrap().len(), MAX_STEPS);
+        assert_eq!(error(". ~").column, 3);
+        assert_eq!(error("c4 [d4 [e4]").column, 4);
+        assert_eq!(error("c4 d4]").column, 6);
+
+        assert_eq!(
+            error("a4:8 c5:7").underline("a4:8 c5:7"),
+            "a4:8 c5:7\n        ^ invalid length '7'"
+        );
+    }
+}
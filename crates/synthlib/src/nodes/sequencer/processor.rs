@@ -0,0 +1,1246 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    diff::Patch,
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{AudioNodeProcessor, ProcBuffers, ProcInfo, ProcessStatus},
+};
+
+use super::{
+    ACCENT_OUTPUT, EditTiming, GATE_OUTPUT, MIN_BPM, PITCH_OUTPUT, SequenceStep, SequencerEvent,
+    SequencerNode, SequencerNodePatch, StepEdit, StepLength, TRIGGER_OUTPUT, VELOCITY_OUTPUT,
+    glide::{Glide, GlideCurve, GlideMode},
+    groove::{STRAIGHT_SWING, swing_ticks},
+    transport::{PlayMode, SequencerState, Transport, TransportState},
+};
+use crate::{cv::HeldPitch, pitch::DEFAULT_A4_HZ, rng::Rng, tuning::Tuning};
+
+/// Step positions are tracked in whole frames, with the fraction of a frame left
+/// at the end of each step carried into the next in units of this many ticks.
+/// It is a multiple of 1000 so that steps in milliseconds are exact at any
+/// sample rate.
+pub(crate) const TICKS_PER_FRAME: u64 = 1_000_000;
+
+/// The number of edits that can wait for the next loop
+const MAX_PENDING_EDITS: usize = 64;
+
+#[derive(Debug)]
+pub struct SequencerProcessor {
+    steps: Vec<SequenceStep>,
+    bpm: f32,
+    a4_hz: f32,
+    /// Replaces `a4_hz` for MIDI notes when set
+    tuning: Option<Box<Tuning>>,
+    sample_rate: u32,
+    current_index: usize,
+    /// The number of frames of the current step that have been played
+    elapsed: u64,
+    /// The length of the current step in frames
+    step_frames: u64,
+    /// The number of frames into the current step that the note starts
+    delay_frames: u64,
+    /// The number of frames into the current step that the gate is released
+    gate_frames: u64,
+    /// The part of a frame, in ticks, that the previous steps ran over by
+    carry: u64,
+    /// Edits to apply when the sequence next loops
+    pending_edits: Vec<StepEdit>,
+    /// A replacement sequence to swap in when the sequence next loops. After the
+    /// swap this holds the old sequence until it can be handed back in an event.
+    pending_steps: Vec<SequenceStep>,
+    has_pending_steps: bool,
+    /// An error from applying the pending edits, to be logged after processing
+    edit_error: Option<&'static str>,
+    play_mode: PlayMode,
+    loop_start: u32,
+    loop_end: Option<u32>,
+    /// The number of steps left before the play mode has been through the loop
+    /// region once
+    steps_until_loop: usize,
+    /// Whether ping-pong is playing backwards
+    reversed: bool,
+    rng: Rng,
+    transport: TransportState,
+    state: SequencerState,
+    glide_ms: f32,
+    glide_curve: GlideCurve,
+    glide_mode: GlideMode,
+    glide: Glide,
+    /// Whether the current step continues the note from the step before
+    legato: bool,
+    /// Whether the current step should glide in from the step before
+    slide_in: bool,
+    /// The last frequency that was output
+    frequency: f32,
+    /// The pitch output, which holds through pauses
+    pitch: HeldPitch,
+    swing: f32,
+    humanise: f32,
+    fill: bool,
+    /// The number of passes through the loop region that have been played
+    loop_count: u32,
+    /// The number of steps into the current pass through the loop region
+    loop_position: usize,
+    /// Whether the note of the current step has been skipped by its probability
+    /// or condition
+    muted: bool,
+    /// The output at the end of the step before, which is held while a note is
+    /// delayed
+    held: StepOutput,
+}
+
+impl SequencerProcessor {
+    pub fn new(steps: Vec<SequenceStep>, bpm: f32, sample_rate: u32) -> Self {
+        let mut processor = Self {
+            steps: if steps.is_empty() {
+                vec![SequenceStep::pause(StepLength::Ms(1000))]
+            } else {
+                steps
+            },
+            bpm: bpm.max(MIN_BPM),
+            a4_hz: DEFAULT_A4_HZ,
+            tuning: None,
+            sample_rate,
+            current_index: 0,
+            elapsed: 0,
+            step_frames: 0,
+            delay_frames: 0,
+            gate_frames: 0,
+            carry: 0,
+            pending_edits: Vec::with_capacity(MAX_PENDING_EDITS),
+            pending_steps: Vec::new(),
+            has_pending_steps: false,
+            edit_error: None,
+            play_mode: PlayMode::default(),
+            loop_start: 0,
+            loop_end: None,
+            steps_until_loop: 0,
+            reversed: false,
+            rng: Rng::default(),
+            transport: TransportState::default(),
+            state: SequencerState::default(),
+            glide_ms: 0.0,
+            glide_curve: GlideCurve::default(),
+            glide_mode: GlideMode::default(),
+            glide: Glide::default(),
+            legato: false,
+            slide_in: false,
+            frequency: 0.0,
+            pitch: HeldPitch::default(),
+            swing: STRAIGHT_SWING,
+            humanise: 0.0,
+            fill: false,
+            loop_count: 0,
+            loop_position: 0,
+            muted: false,
+            held: StepOutput::default(),
+        };
+        processor.restart_loop();
+        processor.start_step();
+        processor
+    }
+
+    pub fn set_play_mode(&mut self, play_mode: PlayMode) {
+        self.play_mode = play_mode;
+        self.steps_until_loop = self.loop_steps();
+    }
+
+    /// Sets the steps that are played, from `start` up to but not including `end`
+    pub fn set_loop_region(&mut self, start: u32, end: Option<u32>) {
+        self.loop_start = start;
+        self.loop_end = end;
+        self.steps_until_loop = self.loop_steps();
+    }
+
+    pub fn set_glide(&mut self, glide_ms: f32, curve: GlideCurve, mode: GlideMode) {
+        self.glide_ms = glide_ms;
+        self.glide_curve = curve;
+        self.glide_mode = mode;
+    }
+
+    /// Sets the swing and humanise in percent, and whether fills are played. The
+    /// change takes effect from the next step.
+    pub fn set_groove(&mut self, swing: f32, humanise: f32, fill: bool) {
+        self.set_swing(swing);
+        self.set_humanise(humanise);
+        self.set_fill(fill);
+    }
+
+    pub fn set_swing(&mut self, swing: f32) {
+        self.swing = swing;
+    }
+
+    pub fn set_humanise(&mut self, humanise: f32) {
+        self.humanise = humanise.max(0.0);
+    }
+
+    pub fn set_fill(&mut self, fill: bool) {
+        self.fill = fill;
+    }
+
+    pub fn set_seed(&mut self, seed: u32) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Sets the state that playback is reported to
+    pub fn set_shared_state(&mut self, state: SequencerState) {
+        self.state = state;
+        self.report_state();
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.transport == TransportState::Playing
+    }
+
+    pub fn transport(&mut self, command: Transport) {
+        match command {
+            Transport::Start => {
+                if self.transport == TransportState::Stopped {
+                    self.reset();
+                }
+                self.transport = TransportState::Playing;
+                self.state.set_finished(false);
+            }
+            Transport::Stop => {
+                self.transport = TransportState::Stopped;
+                self.reset();
+            }
+            Transport::Pause => {
+                if self.transport == TransportState::Playing {
+                    self.transport = TransportState::Paused;
+                }
+            }
+            Transport::Reset => {
+                self.reset();
+                self.state.set_finished(false);
+            }
+        }
+
+        self.report_state();
+    }
+
+    /// Goes back to the start of the loop region
+    fn reset(&mut self) {
+        self.carry = 0;
+        self.legato = false;
+        self.slide_in = false;
+        self.glide = Glide::default();
+        self.held = StepOutput::default();
+        self.loop_count = 0;
+        self.loop_position = 0;
+        self.restart_loop();
+        self.start_step();
+    }
+
+    fn report_state(&self) {
+        self.state
+            .set_playing(self.is_playing(), self.current_index);
+    }
+
+    /// The loop region, clamped to the steps in the sequence so that it always
+    /// holds at least one step
+    fn loop_region(&self) -> (usize, usize) {
+        let len = self.steps.len();
+        let start = (self.loop_start as usize).min(len - 1);
+        let end = self
+            .loop_end
+            .map_or(len, |end| end as usize)
+            .clamp(start + 1, len);
+        (start, end)
+    }
+
+    /// The number of steps played in one pass through the loop region
+    fn loop_steps(&self) -> usize {
+        let (start, end) = self.loop_region();
+        let len = end - start;
+
+        match self.play_mode {
+            PlayMode::PingPong => (2 * (len - 1)).max(1),
+            _ => len,
+        }
+    }
+
+    /// Moves to the first step of a new pass through the loop region
+    fn restart_loop(&mut self) {
+        let (start, end) = self.loop_region();
+
+        self.reversed = false;
+        self.steps_until_loop = self.loop_steps();
+        self.current_index = match self.play_mode {
+            PlayMode::Loop | PlayMode::OneShot | PlayMode::PingPong | PlayMode::RandomWalk => start,
+            PlayMode::Reverse => end - 1,
+            PlayMode::Random => start + self.rng.next_below((end - start) as u32) as usize,
+        };
+    }
+
+    /// The step after the current one in the play mode's order
+    fn next_index(&mut self) -> usize {
+        let (start, end) = self.loop_region();
+        let current = self.current_index;
+
+        // the current step can be outside of the region after an edit
+        if current < start || current >= end {
+            return start;
+        }
+
+        let forward = |i: usize| if i + 1 >= end { start } else { i + 1 };
+        let backward = |i: usize| if i <= start { end - 1 } else { i - 1 };
+
+        match self.play_mode {
+            PlayMode::Loop | PlayMode::OneShot => forward(current),
+            PlayMode::Reverse => backward(current),
+            PlayMode::PingPong => {
+                if end - start == 1 {
+                    return start;
+                }
+
+                if (!self.reversed && current + 1 >= end) || (self.reversed && current <= start) {
+                    self.reversed = !self.reversed;
+                }
+
+                if self.reversed {
+                    current - 1
+                } else {
+                    current + 1
+                }
+            }
+            PlayMode::Random => start + self.rng.next_below((end - start) as u32) as usize,
+            PlayMode::RandomWalk => {
+                if self.rng.next_u32() & 1 == 0 {
+                    forward(current)
+                } else {
+                    backward(current)
+                }
+            }
+        }
+    }
+
+    /// Moves on to the next step once the current one has finished
+    fn advance(&mut self) {
+        let previous_legato = self.steps[self.current_index].is_legato() && !self.muted;
+        let previous_slide = self.steps[self.current_index].slide;
+        self.steps_until_loop = self.steps_until_loop.saturating_sub(1);
+        self.loop_position += 1;
+
+        if self.steps_until_loop == 0 {
+            // a full pass through the loop region has been played
+            self.loop_count = self.loop_count.wrapping_add(1);
+            self.loop_position = 0;
+
+            if let Err(e) = self.apply_pending() {
+                self.edit_error = Some(e);
+            }
+
+            if self.play_mode == PlayMode::OneShot {
+                self.transport = TransportState::Stopped;
+                self.carry = 0;
+                self.state.set_finished(true);
+            }
+
+            if self.play_mode == PlayMode::RandomWalk && self.is_playing() {
+                // a random walk carries on from where it is rather than jumping back
+                self.steps_until_loop = self.loop_steps();
+                self.current_index = self.next_index();
+            } else {
+                self.restart_loop();
+            }
+        } else {
+            self.current_index = self.next_index();
+        }
+
+        self.start_step();
+
+        let sounding = self.steps[self.current_index].pitch.is_some() && !self.muted;
+        self.legato = self.is_playing() && sounding && previous_legato;
+        self.slide_in = self.legato && previous_slide;
+    }
+
+    pub fn set_bpm(&mut self, bpm: f32) {
+        let bpm = bpm.max(MIN_BPM);
+
+        // steps in milliseconds don't depend on the tempo
+        if !matches!(self.steps[self.current_index].length, StepLength::Ms(_)) {
+            self.stretch_remaining(self.bpm as f64 / bpm as f64);
+        }
+
+        self.bpm = bpm;
+    }
+
+    pub fn set_a4_hz(&mut self, a4_hz: f32) {
+        self.a4_hz = a4_hz;
+    }
+
+    /// Plays MIDI notes in a tuning instead of equal temperament from `a4_hz`.
+    /// Notes the tuning doesn't map are played as pauses.
+    pub fn set_tuning(&mut self, tuning: Option<Box<Tuning>>) {
+        self.tuning = tuning;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.stretch_remaining(sample_rate as f64 / self.sample_rate as f64);
+            self.sample_rate = sample_rate;
+        }
+    }
+
+    /// Applies an event, or queues it up until the next loop. Nothing is allocated
+    /// or dropped here, so this is safe to call from the audio thread.
+    pub fn handle_event(&mut self, event: &mut SequencerEvent) -> Result<(), &'static str> {
+        match event {
+            SequencerEvent::Edit {
+                edit,
+                timing: EditTiming::Immediate,
+            } => self.apply_edit(*edit),
+            SequencerEvent::Edit {
+                edit,
+                timing: EditTiming::NextLoop,
+            } => {
+                if self.pending_edits.len() == self.pending_edits.capacity() {
+                    return Err("too many sequencer edits waiting for the next loop");
+                }
+
+                self.pending_edits.push(*edit);
+                Ok(())
+            }
+            SequencerEvent::Transport(command) => {
+                self.transport(*command);
+                Ok(())
+            }
+            SequencerEvent::Retune(tuning) => {
+                // the previous tuning is swapped into the event to be dropped off the
+                // audio thread
+                std::mem::swap(&mut self.tuning, tuning);
+                Ok(())
+            }
+            SequencerEvent::Replace { steps, timing } => {
+                if steps.is_empty() {
+                    return Err("a sequence needs at least one step");
+                }
+
+                // the previous steps are swapped into the event, which is dropped
+                // off the audio thread
+                match timing {
+                    EditTiming::Immediate => {
+                        std::mem::swap(&mut self.steps, steps);
+
+                        if self.current_index >= self.steps.len() {
+                            self.current_index = 0;
+                            self.start_step();
+                        }
+                        self.steps_until_loop = self.loop_steps();
+                    }
+                    EditTiming::NextLoop => {
+                        std::mem::swap(&mut self.pending_steps, steps);
+                        self.has_pending_steps = true;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_edit(&mut self, edit: StepEdit) -> Result<(), &'static str> {
+        match edit {
+            StepEdit::Set { index, step } => {
+                *self
+                    .steps
+                    .get_mut(index)
+                    .ok_or("sequencer step index out of range")? = step;
+            }
+            StepEdit::Insert { index, step } => {
+                if index > self.steps.len() {
+                    return Err("sequencer step index out of range");
+                }
+                if self.steps.len() == self.steps.capacity() {
+                    return Err("the sequence has no room for more steps");
+                }
+
+                self.steps.insert(index, step);
+
+                if index <= self.current_index {
+                    self.current_index += 1;
+                }
+            }
+            StepEdit::Remove { index } => {
+                if index >= self.steps.len() {
+                    return Err("sequencer step index out of range");
+                }
+                if self.steps.len() == 1 {
+                    return Err("a sequence needs at least one step");
+                }
+
+                self.steps.remove(index);
+
+                if index < self.current_index {
+                    self.current_index -= 1;
+                } else if index == self.current_index {
+                    // move straight on to the step after the removed one
+                    if self.current_index == self.steps.len() {
+                        self.current_index = 0;
+                    }
+                    self.start_step();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies everything that was waiting for the sequence to loop
+    fn apply_pending(&mut self) -> Result<(), &'static str> {
+        if self.has_pending_steps {
+            std::mem::swap(&mut self.steps, &mut self.pending_steps);
+            self.has_pending_steps = false;
+        }
+
+        let mut result = Ok(());
+        for i in 0..self.pending_edits.len() {
+            result = result.and(self.apply_edit(self.pending_edits[i]));
+        }
+        self.pending_edits.clear();
+
+        result
+    }
+
+    /// The exact length of a step in ticks
+    fn step_ticks(&self, step: &SequenceStep) -> u64 {
+        match step.length {
+            StepLength::Ms(ms) => ms as u64 * self.sample_rate as u64 * (TICKS_PER_FRAME / 1000),
+            length => {
+                let frames = length.seconds(self.bpm) * self.sample_rate as f64;
+                (frames * TICKS_PER_FRAME as f64).round() as u64
+            }
+        }
+    }
+
+    /// Works out the length of the current step in whole frames, carrying the
+    /// remainder into the next step so that rounding errors never build up, and
+    /// decides whether and when its note plays.
+    fn start_step(&mut self) {
+        let step = self.steps[self.current_index];
+        let ticks =
+            swing_ticks(self.step_ticks(&step), self.swing, self.loop_position) + self.carry;
+
+        // Every step lasts at least one frame so that a sequence of empty steps
+        // can't stall
+        self.step_frames = (ticks / TICKS_PER_FRAME).max(1);
+        self.carry = ticks.saturating_sub(self.step_frames * TICKS_PER_FRAME);
+
+        // the random number generator is only used when it's needed, so that adding
+        // humanise or probability doesn't change the order of a random play mode
+        // until it's turned on
+        let mut offset_percent = step.offset_percent;
+        if self.humanise > 0.0 {
+            offset_percent += self.humanise * self.rng.next_f32();
+        }
+        self.delay_frames = percent_of_frames(self.step_frames, offset_percent);
+
+        self.muted = !step.condition.is_met(self.loop_count, self.fill)
+            || (step.probability < 100.0 && self.rng.next_f32() * 100.0 >= step.probability);
+
+        let note_frames = self.step_frames - self.delay_frames;
+        self.gate_frames = self.delay_frames
+            + if step.is_legato() {
+                note_frames
+            } else {
+                percent_of_frames(note_frames, step.gate_percent)
+            };
+        self.elapsed = 0;
+    }
+
+    /// Scales the part of the current step that hasn't been played yet, for
+    /// example when the tempo changes halfway through a step.
+    fn stretch_remaining(&mut self, ratio: f64) {
+        let stretch = |frames: u64| (frames as f64 * ratio).round() as u64;
+
+        if self.delay_frames > self.elapsed {
+            self.delay_frames = self.elapsed + stretch(self.delay_frames - self.elapsed);
+        }
+
+        if self.gate_frames > self.elapsed {
+            self.gate_frames = self.elapsed + stretch(self.gate_frames - self.elapsed);
+        }
+
+        self.step_frames = self.elapsed + stretch(self.step_frames - self.elapsed).max(1);
+        self.delay_frames = self.delay_frames.min(self.step_frames);
+        self.gate_frames = self.gate_frames.min(self.step_frames);
+        self.carry = 0;
+    }
+
+    /// Plays the next `frames` frames into `outputs` from the frame `start`, with
+    /// the outputs in the order of [PITCH_OUTPUT] to [ACCENT_OUTPUT]
+    pub(super) fn render(&mut self, outputs: &mut [&mut [f32]], start: usize, frames: usize) {
+        let mut sample_count = frames;
+        let mut current_sample_idx = start;
+
+        while sample_count > 0 {
+            let (samples, output) = self.get_samples(sample_count);
+            let range = current_sample_idx..(current_sample_idx + samples);
+
+            let gate = if output.gate { 1.0 } else { 0.0 };
+            let accent = if output.accent { 1.0 } else { 0.0 };
+
+            // the glide runs in hertz, and is then turned into volts
+            let pitch_out = &mut outputs[PITCH_OUTPUT as usize][range.clone()];
+            self.glide.fill(pitch_out, output.frequency);
+            self.frequency = pitch_out.last().copied().unwrap_or(self.frequency);
+            for pitch in pitch_out.iter_mut() {
+                *pitch = self.pitch.voltage(*pitch);
+            }
+
+            outputs[GATE_OUTPUT as usize][range.clone()].fill(gate);
+            outputs[TRIGGER_OUTPUT as usize][range.clone()].fill(0.0);
+            outputs[VELOCITY_OUTPUT as usize][range.clone()].fill(output.velocity);
+            outputs[ACCENT_OUTPUT as usize][range].fill(accent);
+
+            if output.trigger {
+                outputs[TRIGGER_OUTPUT as usize][current_sample_idx] = 1.0;
+            }
+
+            current_sample_idx += samples;
+            sample_count -= samples;
+        }
+    }
+
+    /// Swaps in a different sequence and goes back to the start of it. The old
+    /// sequence is left in `steps`.
+    pub(super) fn swap_steps(&mut self, steps: &mut Vec<SequenceStep>) {
+        if steps.is_empty() {
+            return;
+        }
+
+        std::mem::swap(&mut self.steps, steps);
+        self.reset();
+    }
+
+    /// Returns the next sequence to play, with the given number of frames
+    /// and the values to output. Stops at "sequence" boundaries and at the end of
+    /// the gate, meaning that this function should be called until the number of
+    /// samples returned is 0
+    fn get_samples(&mut self, samples: usize) -> (usize, StepOutput) {
+        if samples == 0 {
+            return (0, StepOutput::default());
+        }
+
+        // a one-shot sequence that has finished is silent for the rest of the block
+        if !self.is_playing() {
+            return (samples, StepOutput::default());
+        }
+
+        // a delayed note carries on from the step before until it starts
+        if self.elapsed < self.delay_frames {
+            let num_samples = (self.delay_frames - self.elapsed).min(samples as u64);
+            self.elapsed += num_samples;
+
+            let output = StepOutput {
+                trigger: false,
+                ..self.held
+            };
+            return (num_samples as usize, output);
+        }
+
+        let step = self.steps[self.current_index];
+        let frequency = match &self.tuning {
+            Some(tuning) => step.pitch.and_then(|pitch| pitch.tuned_hz(tuning)),
+            None => step.frequency(self.a4_hz),
+        };
+        let sounding = frequency.is_some() && !self.muted;
+        let frequency = frequency.unwrap_or_default();
+
+        if self.elapsed == self.delay_frames {
+            // the glide is worked out here rather than when the step starts so that
+            // it begins from the last frequency that was actually output
+            self.glide = if self.slide_in {
+                Glide::start(
+                    self.frequency,
+                    frequency,
+                    self.glide_ms,
+                    self.glide_curve,
+                    self.glide_mode,
+                    self.sample_rate,
+                )
+            } else {
+                Glide::default()
+            };
+        }
+
+        // notes are split in two, with the gate held for the first part
+        let gate = sounding && self.elapsed < self.gate_frames;
+        let segment = if gate {
+            self.gate_frames - self.elapsed
+        } else {
+            self.step_frames - self.elapsed
+        };
+
+        let output = StepOutput {
+            frequency,
+            gate,
+            trigger: gate && self.elapsed == self.delay_frames && !self.legato,
+            velocity: if sounding { step.velocity } else { 0.0 },
+            accent: sounding && step.accent,
+        };
+        self.held = output;
+
+        let num_samples = segment.min(samples as u64);
+        self.elapsed += num_samples;
+
+        if self.elapsed >= self.step_frames {
+            self.advance();
+        }
+
+        (num_samples as usize, output)
+    }
+}
+
+/// The number of frames in a percentage of a step of the given length
+#[inline]
+fn percent_of_frames(step_frames: u64, percent: f32) -> u64 {
+    let frames = (step_frames as f64 * percent.clamp(0.0, 100.0) as f64 / 100.0).round();
+    (frames as u64).min(step_frames)
+}
+
+/// The values output by the sequencer for part of a step
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct StepOutput {
+    frequency: f32,
+    gate: bool,
+    /// Whether the first sample starts a new note
+    trigger: bool,
+    velocity: f32,
+    accent: bool,
+}
+
+impl AudioNodeProcessor for SequencerProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for mut event in events.drain() {
+            if let Some(patch) = SequencerNode::patch_event(&event) {
+                match patch {
+                    SequencerNodePatch::Bpm(bpm) => self.set_bpm(bpm),
+                    SequencerNodePatch::A4Hz(a4_hz) => self.set_a4_hz(a4_hz),
+                    SequencerNodePatch::PlayMode(play_mode) => self.set_play_mode(play_mode),
+                    SequencerNodePatch::LoopStart(start) => {
+                        self.set_loop_region(start, self.loop_end);
+                    }
+                    SequencerNodePatch::LoopEnd(end) => self.set_loop_region(self.loop_start, end),
+                    SequencerNodePatch::GlideMs(glide_ms) => self.glide_ms = glide_ms,
+                    SequencerNodePatch::GlideCurve(curve) => self.glide_curve = curve,
+                    SequencerNodePatch::GlideMode(mode) => self.glide_mode = mode,
+                    SequencerNodePatch::Swing(swing) => self.set_swing(swing),
+                    SequencerNodePatch::Humanise(humanise) => self.set_humanise(humanise),
+                    SequencerNodePatch::Fill(fill) => self.set_fill(fill),
+                }
+            } else if let Some(event) = event.downcast_mut::<SequencerEvent>()
+                && let Err(e) = self.handle_event(event)
+            {
+                let _ = logger.try_error(e);
+            }
+        }
+
+        if !self.is_playing() {
+            self.report_state();
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        self.render(buffers.outputs, 0, proc_info.frames);
+
+        if let Some(e) = self.edit_error.take() {
+            let _ = logger.try_error(e);
+        }
+
+        self.report_state();
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.set_sample_rate(stream_info.sample_rate.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::groove::StepCondition;
+    use super::*;
+    use crate::{
+        pitch::{Pitch, midi_to_hz},
+        tempo::NoteValue,
+        tuning::{KeyboardMapping, ScalaScale},
+    };
+
+    #[test]
+    fn test_step_lengths() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::note(440.0, NoteValue::QUARTER),
+                SequenceStep::note(220.0, StepLength::Beats(0.5)),
+                SequenceStep::pause(StepLength::Ms(100)),
+            ],
+            120.0,
+            48_000,
+        );
+
+        let next = |processor: &mut SequencerProcessor, samples| {
+            let (samples, output) = processor.get_samples(samples);
+            (samples, output.frequency)
+        };
+
+        assert_eq!(next(&mut processor, 100_000), (24_000, 440.0));
+        assert_eq!(next(&mut processor, 100_000), (12_000, 220.0));
+        assert_eq!(next(&mut processor, 100_000), (4_800, 0.0));
+
+        // changing the tempo halfway through a step stretches what is left of it
+        assert_eq!(next(&mut processor, 12_000), (12_000, 440.0));
+        processor.set_bpm(60.0);
+        assert_eq!(next(&mut processor, 100_000), (24_000, 440.0));
+        assert_eq!(next(&mut processor, 100_000), (24_000, 220.0));
+        assert_eq!(next(&mut processor, 100_000), (4_800, 0.0));
+    }
+
+    #[test]
+    fn test_gate_and_trigger() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::QUARTER)
+                    .with_gate(25.0)
+                    .with_velocity(0.5)
+                    .with_accent(true),
+                SequenceStep::pause(NoteValue::QUARTER),
+            ],
+            120.0,
+            48_000,
+        );
+
+        // the gate is held for the first quarter of the note
+        let (samples, output) = processor.get_samples(100_000);
+        assert_eq!(samples, 6_000);
+        assert!(output.gate && output.trigger && output.accent);
+        assert_eq!(output.velocity, 0.5);
+
+        let (samples, output) = processor.get_samples(100_000);
+        assert_eq!(samples, 18_000);
+        assert!(!output.gate && !output.trigger);
+
+        // rests never open the gate
+        let (samples, output) = processor.get_samples(100_000);
+        assert_eq!(samples, 24_000);
+        assert!(!output.gate && !output.trigger && !output.accent);
+        assert_eq!(output.velocity, 0.0);
+    }
+
+    /// Plays `steps` steps in blocks of `block_size` frames, returning the frame
+    /// that each step started on
+    fn step_starts(
+        processor: &mut SequencerProcessor,
+        steps: usize,
+        block_size: usize,
+    ) -> Vec<u64> {
+        let mut starts = vec![0];
+        let mut frame = 0;
+
+        while starts.len() <= steps {
+            let mut remaining = block_size;
+            while remaining > 0 {
+                let index = processor.current_index;
+                let (samples, _) = processor.get_samples(remaining);
+
+                frame += samples as u64;
+                remaining -= samples;
+
+                if processor.current_index != index {
+                    starts.push(frame);
+                }
+            }
+        }
+
+        starts.truncate(steps + 1);
+        starts
+    }
+
+    #[test]
+    fn test_ms_steps_are_exact() {
+        for sample_rate in [22_050, 44_100, 48_000, 88_200, 96_000, 192_000] {
+            for ms in [1, 7, 333, 1000] {
+                let steps = vec![
+                    SequenceStep::midi(60, StepLength::Ms(ms)),
+                    SequenceStep::pause(StepLength::Ms(ms)),
+                ];
+                let mut processor = SequencerProcessor::new(steps, 120.0, sample_rate);
+
+                let starts = step_starts(&mut processor, 10_000, 1021);
+
+                for (k, start) in starts.iter().enumerate() {
+                    let expected = k as u64 * ms as u64 * sample_rate as u64 / 1000;
+                    assert_eq!(*start, expected, "step {k} of {ms} ms at {sample_rate} Hz");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_musical_steps_do_not_drift() {
+        // a dotted sixteenth at 133 bpm is not a whole number of frames
+        let length = NoteValue::SIXTEENTH.dotted();
+        let exact = length.seconds(133.0) * 44_100.0;
+        let steps = vec![SequenceStep::midi(60, length), SequenceStep::pause(length)];
+        let mut processor = SequencerProcessor::new(steps, 133.0, 44_100);
+
+        let starts = step_starts(&mut processor, 200_000, 512);
+
+        for (k, start) in starts.iter().enumerate() {
+            let error = *start as f64 - k as f64 * exact;
+            assert!(error.abs() < 1.0, "step {k} is {error} frames out");
+        }
+    }
+
+    fn pitches(processor: &SequencerProcessor) -> Vec<Option<Pitch>> {
+        processor.steps.iter().map(SequenceStep::pitch).collect()
+    }
+
+    #[test]
+    fn test_immediate_edits() {
+        let mut steps = Vec::with_capacity(3);
+        steps.extend([
+            SequenceStep::midi(60, NoteValue::QUARTER),
+            SequenceStep::midi(62, NoteValue::QUARTER),
+        ]);
+        let mut processor = SequencerProcessor::new(steps, 120.0, 48_000);
+
+        // play into the second step
+        processor.get_samples(24_000);
+        processor.get_samples(100);
+        assert_eq!(processor.current_index, 1);
+
+        let edit = |processor: &mut SequencerProcessor, edit| {
+            processor.handle_event(&mut SequencerEvent::Edit {
+                edit,
+                timing: EditTiming::Immediate,
+            })
+        };
+        let step = SequenceStep::midi(64, NoteValue::QUARTER);
+
+        // inserting before the playing step keeps it playing
+        assert_eq!(
+            edit(&mut processor, StepEdit::Insert { index: 0, step }),
+            Ok(())
+        );
+        assert_eq!(processor.current_index, 2);
+        assert_eq!(processor.elapsed, 100);
+        assert_eq!(
+            edit(&mut processor, StepEdit::Insert { index: 0, step }),
+            Err("the sequence has no room for more steps")
+        );
+
+        // removing the playing step moves on to the next one
+        assert_eq!(edit(&mut processor, StepEdit::Remove { index: 2 }), Ok(()));
+        assert_eq!(processor.current_index, 0);
+        assert_eq!(processor.elapsed, 0);
+        assert_eq!(
+            pitches(&processor),
+            [Some(Pitch::Midi(64.0)), Some(Pitch::Midi(60.0))]
+        );
+
+        assert_eq!(
+            edit(
+                &mut processor,
+                StepEdit::Set {
+                    index: 1,
+                    step: SequenceStep::pause(NoteValue::QUARTER)
+                }
+            ),
+            Ok(())
+        );
+        assert_eq!(pitches(&processor), [Some(Pitch::Midi(64.0)), None]);
+    }
+
+    #[test]
+    fn test_next_loop_edits() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::QUARTER),
+                SequenceStep::midi(62, NoteValue::QUARTER),
+            ],
+            120.0,
+            48_000,
+        );
+
+        let replacement = vec![SequenceStep::midi(72, NoteValue::QUARTER)];
+        let mut event = SequencerEvent::Replace {
+            steps: replacement,
+            timing: EditTiming::NextLoop,
+        };
+        assert_eq!(processor.handle_event(&mut event), Ok(()));
+        assert_eq!(
+            processor.handle_event(&mut SequencerEvent::Edit {
+                edit: StepEdit::Insert {
+                    index: 0,
+                    step: SequenceStep::pause(NoteValue::QUARTER),
+                },
+                timing: EditTiming::NextLoop,
+            }),
+            Ok(())
+        );
+
+        // nothing changes until the sequence loops
+        processor.get_samples(24_000);
+        assert_eq!(
+            pitches(&processor),
+            [Some(Pitch::Midi(60.0)), Some(Pitch::Midi(62.0))]
+        );
+        processor.get_samples(24_000);
+
+        // the insert had no room in the replacement, so only the replacement happens
+        assert_eq!(
+            processor.edit_error,
+            Some("the sequence has no room for more steps")
+        );
+        assert_eq!(pitches(&processor), [Some(Pitch::Midi(72.0))]);
+        assert_eq!(processor.current_index, 0);
+    }
+
+    /// Plays `count` steps, returning the index of each
+    fn play_order(processor: &mut SequencerProcessor, count: usize) -> Vec<usize> {
+        (0..count)
+            .map(|_| {
+                let index = processor.current_index;
+                processor.get_samples(usize::MAX);
+                index
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_play_modes() {
+        let steps = (60..65)
+            .map(|note| SequenceStep::midi(note, NoteValue::SIXTEENTH))
+            .collect();
+        let mut processor = SequencerProcessor::new(steps, 120.0, 48_000);
+
+        assert_eq!(play_order(&mut processor, 7), [0, 1, 2, 3, 4, 0, 1]);
+
+        processor.set_play_mode(PlayMode::Reverse);
+        processor.transport(Transport::Reset);
+        assert_eq!(play_order(&mut processor, 7), [4, 3, 2, 1, 0, 4, 3]);
+
+        processor.set_play_mode(PlayMode::PingPong);
+        processor.set_loop_region(1, Some(4));
+        processor.transport(Transport::Reset);
+        assert_eq!(play_order(&mut processor, 7), [1, 2, 3, 2, 1, 2, 3]);
+
+        processor.set_play_mode(PlayMode::RandomWalk);
+        processor.transport(Transport::Reset);
+        let order = play_order(&mut processor, 100);
+        assert!(order.iter().all(|i| (1..4).contains(i)));
+        assert!(
+            order
+                .windows(2)
+                .all(|w| w[0].abs_diff(w[1]) == 1 || w[0].abs_diff(w[1]) == 2)
+        );
+    }
+
+    #[test]
+    fn test_one_shot_finishes() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::QUARTER),
+                SequenceStep::midi(62, NoteValue::QUARTER),
+            ],
+            120.0,
+            48_000,
+        );
+        let state = SequencerState::default();
+        processor.set_shared_state(state.clone());
+        processor.set_play_mode(PlayMode::OneShot);
+
+        assert_eq!(processor.get_samples(100_000).0, 24_000);
+        assert_eq!(processor.get_samples(100_000).0, 24_000);
+        assert!(!processor.is_playing());
+        assert!(state.is_finished());
+        assert_eq!(state.completions(), 1);
+
+        // the rest is silent
+        let (samples, output) = processor.get_samples(1_000);
+        assert_eq!(samples, 1_000);
+        assert!(!output.gate && output.frequency == 0.0);
+
+        // and it can be played again from the start
+        processor.transport(Transport::Start);
+        assert!(!state.is_finished());
+        let (_, output) = processor.get_samples(100_000);
+        assert!(output.trigger && output.frequency > 0.0);
+        assert_eq!(processor.current_index, 1);
+    }
+
+    #[test]
+    fn test_slides_and_ties_hold_the_gate() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(48, NoteValue::SIXTEENTH)
+                    .with_gate(50.0)
+                    .with_slide(true),
+                SequenceStep::midi(60, NoteValue::SIXTEENTH)
+                    .with_gate(50.0)
+                    .with_tie(true),
+                SequenceStep::midi(60, NoteValue::SIXTEENTH).with_gate(50.0),
+                SequenceStep::midi(55, NoteValue::SIXTEENTH).with_gate(50.0),
+            ],
+            120.0,
+            48_000,
+        );
+
+        let mut next = || {
+            let (samples, output) = processor.get_samples(100_000);
+            (samples, output.gate, output.trigger)
+        };
+
+        // the slide and tie hold the gate for the whole step
+        assert_eq!(next(), (6_000, true, true));
+        assert_eq!(next(), (6_000, true, false));
+        // the note after the tie isn't retriggered, and closes its gate as usual
+        assert_eq!(next(), (3_000, true, false));
+        assert_eq!(next(), (3_000, false, false));
+        assert_eq!(next(), (3_000, true, true));
+    }
+
+    #[test]
+    fn test_swing_and_offsets() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::SIXTEENTH).with_gate(50.0),
+                SequenceStep::midi(67, NoteValue::SIXTEENTH)
+                    .with_gate(50.0)
+                    .with_offset(25.0),
+            ],
+            120.0,
+            48_000,
+        );
+        processor.set_groove(60.0, 0.0, false);
+        processor.transport(Transport::Reset);
+
+        let mut next = || {
+            let (samples, output) = processor.get_samples(100_000);
+            (samples, output.gate, output.trigger, output.frequency)
+        };
+
+        // the first step of the pair is lengthened by the swing
+        let c4 = midi_to_hz(60.0, DEFAULT_A4_HZ);
+        let g4 = midi_to_hz(67.0, DEFAULT_A4_HZ);
+        assert_eq!(next(), (3_600, true, true, c4));
+        assert_eq!(next(), (3_600, false, false, c4));
+        // the second is shortened, and its note starts a quarter of the way in
+        assert_eq!(next(), (1_200, false, false, c4));
+        assert_eq!(next(), (1_800, true, true, g4));
+        assert_eq!(next(), (1_800, false, false, g4));
+        assert_eq!(next(), (3_600, true, true, c4));
+    }
+
+    /// Whether each note was triggered over a number of passes through the loop
+    fn triggers(processor: &mut SequencerProcessor, loops: usize) -> Vec<bool> {
+        let steps = processor.steps.len() * loops;
+        let mut triggers = Vec::with_capacity(steps);
+
+        let mut triggered = false;
+        while triggers.len() < steps {
+            let index = processor.current_index;
+            let (_, output) = processor.get_samples(100_000);
+            triggered |= output.trigger;
+
+            if processor.current_index != index {
+                triggers.push(triggered);
+                triggered = false;
+            }
+        }
+
+        triggers
+    }
+
+    #[test]
+    fn test_probability_is_reproducible() {
+        let render = |seed| {
+            let steps = (0..16)
+                .map(|_| SequenceStep::midi(60, NoteValue::SIXTEENTH).with_probability(50.0))
+                .collect();
+            let mut processor = SequencerProcessor::new(steps, 120.0, 48_000);
+            processor.set_groove(STRAIGHT_SWING, 20.0, false);
+            processor.set_seed(seed);
+            processor.transport(Transport::Reset);
+            triggers(&mut processor, 4)
+        };
+
+        let played = render(7);
+        assert_eq!(played, render(7));
+        assert_ne!(played, render(8));
+
+        let count = played.iter().filter(|played| **played).count();
+        assert!((16..48).contains(&count), "{count} of 64 notes played");
+    }
+
+    #[test]
+    fn test_step_conditions() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::SIXTEENTH).with_condition(StepCondition::First),
+                SequenceStep::midi(60, NoteValue::SIXTEENTH)
+                    .with_condition(StepCondition::Every { n: 2, offset: 1 }),
+                SequenceStep::midi(60, NoteValue::SIXTEENTH).with_condition(StepCondition::Fill),
+            ],
+            120.0,
+            48_000,
+        );
+
+        assert_eq!(
+            triggers(&mut processor, 3),
+            [true, false, false, false, true, false, false, false, false]
+        );
+
+        processor.set_groove(STRAIGHT_SWING, 0.0, true);
+        assert_eq!(triggers(&mut processor, 1), [false, true, true]);
+    }
+
+    #[test]
+    fn test_tuning() {
+        let mut processor = SequencerProcessor::new(
+            vec![
+                SequenceStep::midi(60, NoteValue::QUARTER),
+                SequenceStep::midi(61, NoteValue::QUARTER),
+            ],
+            120.0,
+            48_000,
+        );
+
+        // a scale of two notes where only every other key is played
+        let scale = ScalaScale::parse("fifths\n2\n3/2\n2/1\n").unwrap();
+        let mapping = KeyboardMapping {
+            reference_hz: 200.0,
+            map: vec![Some(0), None, Some(1)],
+            octave_degree: 2,
+            ..Default::default()
+        };
+        let tuning = Tuning::new(&scale, &mapping).unwrap();
+        let mut event = SequencerEvent::Retune(Some(Box::new(tuning.clone())));
+        processor.handle_event(&mut event).unwrap();
+        assert_eq!(event, SequencerEvent::Retune(None));
+
+        let (_, output) = processor.get_samples(24_000);
+        assert_eq!(output.frequency, 200.0);
+        let (_, output) = processor.get_samples(24_000);
+        assert!(!output.gate && !output.trigger);
+
+        // going back to equal temperament hands the tuning back
+        let mut event = SequencerEvent::Retune(None);
+        processor.handle_event(&mut event).unwrap();
+        assert_eq!(event, SequencerEvent::Retune(Some(Box::new(tuning))));
+        processor.get_samples(24_000);
+        let (_, output) = processor.get_samples(24_000);
+        assert!(output.gate);
+        assert_eq!(output.frequency, midi_to_hz(61.0, DEFAULT_A4_HZ));
+    }
+}
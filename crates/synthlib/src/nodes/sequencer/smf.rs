@@ -0,0 +1,642 @@
+//! Reading and writing sequences as Standard MIDI Files
+
+use std::fmt;
+
+use super::{SequenceStep, SequencerConfig, StepLength};
+use crate::pitch::{Pitch, hz_to_midi};
+
+/// The resolution of exported files, in ticks per quarter note
+const EXPORT_PPQ: u16 = 480;
+/// The tempo of a file that doesn't set one, in microseconds per quarter note
+const DEFAULT_TEMPO: u32 = 500_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmfError {
+    /// The data doesn't start with a MIDI file header
+    NotMidiFile,
+    /// Only type 0 and type 1 files can be read
+    UnsupportedFormat(u16),
+    /// The file ends partway through a chunk or event
+    UnexpectedEnd,
+    /// A status byte that isn't allowed where it appears
+    InvalidStatus(u8),
+    /// The header's division is an SMPTE frame rate other than 24, 25, 29.97 or
+    /// 30 frames per second, or has no ticks in each frame
+    InvalidDivision(u16),
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotMidiFile => write!(f, "not a standard MIDI file"),
+            Self::UnsupportedFormat(format) => write!(f, "unsupported MIDI file type {format}"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of MIDI file"),
+            Self::InvalidStatus(status) => write!(f, "invalid MIDI status byte {status:#04x}"),
+            Self::InvalidDivision(division) => {
+                write!(f, "invalid MIDI file division {division:#06x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SmfError {}
+
+/// A part read from a MIDI file, made from the notes of one channel of one track
+#[derive(Debug, Clone)]
+pub struct SmfTrack {
+    /// The name of the track, if the file gives one
+    pub name: Option<String>,
+    pub channel: u8,
+    /// The tempo at the start of the file, for [super::SequencerNode::bpm]
+    pub bpm: f32,
+    pub config: SequencerConfig,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Note {
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8,
+}
+
+/// Reads the parts of a type 0 or type 1 MIDI file. Every channel of every track
+/// that plays notes becomes its own sequence.
+///
+/// The sequencer plays one note at a time, so each note is cut short when the
+/// next one starts, and only the highest note of a chord is kept. Files with a
+/// single tempo are read in beats so that the tempo can be changed as they play.
+/// Otherwise the tempo changes are worked into steps in milliseconds.
+pub fn import_smf(data: &[u8]) -> Result<Vec<SmfTrack>, SmfError> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.bytes(4)? != b"MThd" {
+        return Err(SmfError::NotMidiFile);
+    }
+    let header_len = reader.u32()? as usize;
+    let format = reader.u16()?;
+    let num_tracks = reader.u16()?;
+    let division = reader.u16()?;
+    reader.bytes(header_len.saturating_sub(6))?;
+
+    if format > 1 {
+        return Err(SmfError::UnsupportedFormat(format));
+    }
+
+    let mut tempos = Vec::new();
+    let mut parts = Vec::new();
+
+    for _ in 0..num_tracks {
+        // chunks other than tracks are skipped, as the standard asks
+        let mut chunk = loop {
+            let id = reader.bytes(4)?;
+            let len = reader.u32()? as usize;
+            let chunk = reader.bytes(len)?;
+            if id == b"MTrk" {
+                break Reader {
+                    data: chunk,
+                    pos: 0,
+                };
+            }
+        };
+
+        let (name, notes) = read_track(&mut chunk, &mut tempos)?;
+        for channel in 0..16 {
+            let notes: Vec<Note> = notes
+                .iter()
+                .filter(|(c, _)| *c == channel)
+                .map(|(_, note)| *note)
+                .collect();
+            if !notes.is_empty() {
+                parts.push((name.clone(), channel, notes));
+            }
+        }
+    }
+
+    // the tempo map is shared by every track, and is usually in the first
+    tempos.sort_by_key(|(tick, _)| *tick);
+    let timing = Timing::new(division, tempos)?;
+
+    Ok(parts
+        .into_iter()
+        .map(|(name, channel, notes)| {
+            let sequences = notes_to_steps(notes, &timing);
+            SmfTrack {
+                name,
+                channel,
+                bpm: timing.bpm(),
+                config: SequencerConfig {
+                    capacity: sequences.len().max(SequencerConfig::default().capacity),
+                    sequences,
+                    ..Default::default()
+                },
+            }
+        })
+        .collect())
+}
+
+/// The notes of a track with their channels, and the name of the track
+type TrackNotes = (Option<String>, Vec<(u8, Note)>);
+
+fn read_track(chunk: &mut Reader, tempos: &mut Vec<(u64, u32)>) -> Result<TrackNotes, SmfError> {
+    let mut name = None;
+    let mut notes: Vec<(u8, Note)> = Vec::new();
+    // the index in `notes` of the note playing on each key of each channel
+    let mut playing: [[Option<usize>; 128]; 16] = [[None; 128]; 16];
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while chunk.pos < chunk.data.len() {
+        tick += chunk.vlq()? as u64;
+
+        let mut status = chunk.u8()?;
+        if status < 0x80 {
+            // running status reuses the last status, and this byte is data
+            status = running_status.ok_or(SmfError::InvalidStatus(status))?;
+            chunk.pos -= 1;
+        }
+
+        match status {
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let data_len = if matches!(status & 0xF0, 0xC0 | 0xD0) {
+                    1
+                } else {
+                    2
+                };
+                let data = chunk.bytes(data_len)?;
+
+                let kind = status & 0xF0;
+                if kind == 0x80 || kind == 0x90 {
+                    let key = data[0] & 0x7F;
+                    let velocity = data[1] & 0x7F;
+                    let slot = &mut playing[channel as usize][key as usize];
+
+                    // a note on ends the note already playing on the same key
+                    if let Some(i) = slot.take() {
+                        notes[i].1.end = tick;
+                    }
+
+                    if kind == 0x90 && velocity > 0 {
+                        *slot = Some(notes.len());
+                        notes.push((
+                            channel,
+                            Note {
+                                start: tick,
+                                end: tick,
+                                key,
+                                velocity,
+                            },
+                        ));
+                    }
+                }
+            }
+            0xF0 | 0xF7 => {
+                // sysex and meta events cancel running status
+                running_status = None;
+                let len = chunk.vlq()? as usize;
+                chunk.bytes(len)?;
+            }
+            0xFF => {
+                running_status = None;
+                let kind = chunk.u8()?;
+                let len = chunk.vlq()? as usize;
+                let data = chunk.bytes(len)?;
+
+                match kind {
+                    0x03 => name = Some(String::from_utf8_lossy(data).into_owned()),
+                    0x51 if len == 3 => {
+                        let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                        tempos.push((tick, tempo));
+                    }
+                    0x2F => break,
+                    _ => {}
+                }
+            }
+            _ => return Err(SmfError::InvalidStatus(status)),
+        }
+    }
+
+    // notes that are never released end with the track
+    for slot in playing.iter().flatten().flatten() {
+        notes[*slot].1.end = tick;
+    }
+
+    Ok((name, notes))
+}
+
+/// Turns the notes of a part into notes and pauses, one at a time
+fn notes_to_steps(mut notes: Vec<Note>, timing: &Timing) -> Vec<SequenceStep> {
+    // the highest note of a chord sorts last, and replaces the others
+    notes.sort_by_key(|note| (note.start, note.key));
+    notes.dedup_by(|next, note| {
+        let same_start = next.start == note.start;
+        if same_start {
+            *note = *next;
+        }
+        same_start
+    });
+
+    let mut steps = Vec::with_capacity(notes.len() * 2 + 1);
+    let mut tick = 0;
+
+    for (i, note) in notes.iter().enumerate() {
+        let end = match notes.get(i + 1) {
+            Some(next) => note.end.min(next.start),
+            None => note.end,
+        };
+
+        if note.start > tick {
+            steps.push(SequenceStep::pause(timing.length(tick, note.start)));
+        }
+        steps.push(
+            SequenceStep::midi(note.key, timing.length(note.start, end))
+                .with_velocity(note.velocity as f32 / 127.0),
+        );
+        tick = end;
+    }
+
+    steps
+}
+
+/// Converts ticks to step lengths using the division and tempo map of a file
+struct Timing {
+    /// Ticks per quarter note, or `None` for SMPTE timing
+    ppq: Option<u16>,
+    /// Ticks per second for SMPTE timing
+    ticks_per_second: f64,
+    /// The ticks where the tempo changes and the new tempo, in microseconds per
+    /// quarter note
+    tempos: Vec<(u64, u32)>,
+}
+
+impl Timing {
+    fn new(division: u16, tempos: Vec<(u64, u32)>) -> Result<Self, SmfError> {
+        if division & 0x8000 == 0 {
+            return Ok(Self {
+                ppq: Some(division.max(1)),
+                ticks_per_second: 0.0,
+                tempos,
+            });
+        }
+
+        // the top byte is the negative frame rate, and the bottom byte is the
+        // ticks in each frame
+        let frames_per_second = match ((division >> 8) as i8).wrapping_neg() {
+            fps @ (24 | 25 | 30) => fps as f64,
+            29 => 29.97,
+            _ => return Err(SmfError::InvalidDivision(division)),
+        };
+        let ticks_per_frame = division & 0xFF;
+        if ticks_per_frame == 0 {
+            return Err(SmfError::InvalidDivision(division));
+        }
+
+        Ok(Self {
+            ppq: None,
+            ticks_per_second: frames_per_second * ticks_per_frame as f64,
+            tempos,
+        })
+    }
+
+    fn tempo_at(&self, tick: u64) -> u32 {
+        self.tempos
+            .iter()
+            .take_while(|(t, _)| *t <= tick)
+            .last()
+            .map_or(DEFAULT_TEMPO, |(_, tempo)| *tempo)
+    }
+
+    /// The tempo at the start of the file in beats per minute
+    fn bpm(&self) -> f32 {
+        (60_000_000.0 / self.tempo_at(0).max(1) as f64) as f32
+    }
+
+    /// Whether the tempo never changes, so that lengths can be kept in beats
+    fn is_constant(&self) -> bool {
+        self.tempos
+            .iter()
+            .all(|(_, tempo)| *tempo == self.tempo_at(0))
+    }
+
+    /// The time of a tick from the start of the file in milliseconds
+    fn ms(&self, tick: u64) -> f64 {
+        let Some(ppq) = self.ppq else {
+            return tick as f64 / self.ticks_per_second * 1000.0;
+        };
+
+        let mut ms = 0.0;
+        let mut from = 0;
+        let mut tempo = DEFAULT_TEMPO;
+        for (change, new_tempo) in self.tempos.iter().take_while(|(t, _)| *t < tick) {
+            ms += (change - from) as f64 * tempo as f64 / ppq as f64 / 1000.0;
+            from = *change;
+            tempo = *new_tempo;
+        }
+
+        ms + (tick - from) as f64 * tempo as f64 / ppq as f64 / 1000.0
+    }
+
+    fn length(&self, from: u64, to: u64) -> StepLength {
+        match self.ppq {
+            Some(ppq) if self.is_constant() => {
+                StepLength::Beats(((to - from) as f64 / ppq as f64) as f32)
+            }
+            // each end is rounded on its own so that rounding can't build up
+            _ => StepLength::Ms((self.ms(to).round() - self.ms(from).round()) as u32),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SmfError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(SmfError::UnexpectedEnd)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SmfError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SmfError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SmfError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A variable length quantity, with 7 bits in each byte
+    fn vlq(&mut self) -> Result<u32, SmfError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Writes a sequence as a type 0 MIDI file at the given tempo, with MIDI notes
+/// tuned relative to `a4_hz`. Frequencies are rounded to the nearest MIDI note,
+/// and the steps are written as they are, without their probabilities,
+/// conditions or swing. Notes too short to last a tick are left out.
+pub fn export_smf(steps: &[SequenceStep], bpm: f32, a4_hz: f32) -> Vec<u8> {
+    let ticks = |length: StepLength| {
+        let beats = length.seconds(bpm) * bpm as f64 / 60.0;
+        beats * EXPORT_PPQ as f64
+    };
+
+    let mut track = Vec::new();
+    let tempo = (60_000_000.0 / bpm.max(1.0) as f64).round() as u32;
+    track.extend([0x00, 0xFF, 0x51, 0x03]);
+    track.extend(&tempo.to_be_bytes()[1..]);
+
+    // the time of each event is rounded on its own so that rounding can't build up
+    let mut time: f64 = 0.0;
+    let mut last_tick = 0;
+    let mut event = |track: &mut Vec<u8>, tick: u64, bytes: [u8; 3]| {
+        write_vlq(track, tick.saturating_sub(last_tick) as u32);
+        track.extend(bytes);
+        last_tick = tick;
+    };
+
+    for step in steps {
+        let length = ticks(step.length);
+
+        if let Some(pitch) = step.pitch {
+            let note = match pitch {
+                Pitch::Hz(hz) => hz_to_midi(hz, a4_hz),
+                Pitch::Midi(note) => note,
+            };
+            let key = note.round().clamp(0.0, 127.0) as u8;
+            let velocity = (step.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+            let held = if step.is_legato() {
+                length
+            } else {
+                length * step.gate_percent as f64 / 100.0
+            };
+
+            // a note ends by the time the next step starts, so that the events stay
+            // in order, and steps too short to last a tick are left out
+            let start = time.round() as u64;
+            let next_start = (time + length).round() as u64;
+            let end = ((time + held).round() as u64)
+                .max(start + 1)
+                .min(next_start);
+            if end > start {
+                event(&mut track, start, [0x90, key, velocity]);
+                event(&mut track, end, [0x80, key, 0]);
+            }
+        }
+
+        time += length;
+    }
+
+    let end = (time.round() as u64).max(last_tick);
+    write_vlq(&mut track, end.saturating_sub(last_tick) as u32);
+    track.extend([0xFF, 0x2F, 0x00]);
+
+    let mut data = Vec::with_capacity(track.len() + 22);
+    data.extend(b"MThd");
+    data.extend(6u32.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    data.extend(1u16.to_be_bytes());
+    data.extend(EXPORT_PPQ.to_be_bytes());
+    data.extend(b"MTrk");
+    data.extend((track.len() as u32).to_be_bytes());
+    data.extend(track);
+    data
+}
+
+fn write_vlq(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0; 5];
+    let mut len = 0;
+    let mut value = value;
+    loop {
+        bytes[len] = (value & 0x7F) as u8;
+        len += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+
+    for i in (0..len).rev() {
+        data.push(bytes[i] | if i > 0 { 0x80 } else { 0 });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{pitch::DEFAULT_A4_HZ, tempo::NoteValue};
+
+    #[test]
+    fn test_round_trip() {
+        let steps = vec![
+            SequenceStep::midi(60, NoteValue::QUARTER).with_gate(50.0),
+            SequenceStep::pause(NoteValue::EIGHTH),
+            SequenceStep::midi(64, NoteValue::EIGHTH).with_velocity(0.5),
+            SequenceStep::note(440.0, NoteValue::HALF),
+        ];
+
+        let tracks = import_smf(&export_smf(&steps, 90.0, DEFAULT_A4_HZ)).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert!((tracks[0].bpm - 90.0).abs() < 1e-3);
+
+        let imported = &tracks[0].config.sequences;
+        let lengths: Vec<StepLength> = imported.iter().map(SequenceStep::length).collect();
+        assert_eq!(
+            lengths,
+            [0.5, 1.0, 0.5, 2.0].map(StepLength::Beats),
+            "the gap after the short note becomes a pause"
+        );
+
+        let pitches: Vec<Option<Pitch>> = imported.iter().map(SequenceStep::pitch).collect();
+        assert_eq!(
+            pitches,
+            [Some(60.into()), None, Some(64.into()), Some(69.into())]
+        );
+        assert_eq!(imported[2].velocity(), 64.0 / 127.0);
+    }
+
+    #[test]
+    fn test_export_zero_length_steps() {
+        let steps = [
+            SequenceStep::midi(60, StepLength::Ms(0)),
+            SequenceStep::midi(62, StepLength::Beats(1.0)),
+            SequenceStep::midi(64, StepLength::Beats(0.0001)),
+        ];
+
+        // the steps that don't last a tick are left out
+        let tracks = import_smf(&export_smf(&steps, 120.0, DEFAULT_A4_HZ)).unwrap();
+        let imported = &tracks[0].config.sequences;
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].pitch(), Some(62.into()));
+        assert_eq!(imported[0].length(), StepLength::Beats(1.0));
+    }
+
+    /// A type 1 file with a tempo track and a track using running status
+    fn type_1_file() -> Vec<u8> {
+        let tempo_track = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 bpm
+            0x83, 0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 60 bpm after a beat
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let note_track = [
+            0x00, 0xFF, 0x03, 0x04, b'b', b'a', b's', b's', // name
+            0x00, 0x91, 0x24, 0x64, // note on, channel 2
+            0x83, 0x60, 0x24, 0x00, // note off by running status after a beat
+            0x00, 0x28, 0x64, // the next note straight away
+            0x83, 0x60, 0x81, 0x28, 0x00, // a beat later
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        file(480, &[&tempo_track, &note_track])
+    }
+
+    fn file(division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(b"MThd");
+        data.extend(6u32.to_be_bytes());
+        data.extend([0, 1]);
+        data.extend((tracks.len() as u16).to_be_bytes());
+        data.extend(division.to_be_bytes());
+        for track in tracks {
+            data.extend(b"MTrk");
+            data.extend((track.len() as u32).to_be_bytes());
+            data.extend(*track);
+        }
+        data
+    }
+
+    #[test]
+    fn test_tempo_changes() {
+        let tracks = import_smf(&type_1_file()).unwrap();
+        assert_eq!(tracks.len(), 1);
+
+        let track = &tracks[0];
+        assert_eq!(track.name.as_deref(), Some("bass"));
+        assert_eq!(track.channel, 1);
+        assert_eq!(track.bpm, 120.0);
+
+        // the second beat is at half the tempo
+        let lengths: Vec<StepLength> = track
+            .config
+            .sequences
+            .iter()
+            .map(SequenceStep::length)
+            .collect();
+        assert_eq!(lengths, [StepLength::Ms(500), StepLength::Ms(1000)]);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert_eq!(import_smf(b"RIFF").unwrap_err(), SmfError::NotMidiFile);
+        assert_eq!(import_smf(b"MThd").unwrap_err(), SmfError::UnexpectedEnd);
+
+        let mut data = type_1_file();
+        data[9] = 2;
+        assert_eq!(
+            import_smf(&data).unwrap_err(),
+            SmfError::UnsupportedFormat(2)
+        );
+
+        let data = type_1_file();
+        assert_eq!(
+            import_smf(&data[..data.len() - 4]).unwrap_err(),
+            SmfError::UnexpectedEnd
+        );
+    }
+
+    #[test]
+    fn test_smpte_division() {
+        // a half second note at 25 frames per second and 40 ticks per frame
+        let track = [
+            0x00, 0x90, 0x3C, 0x64, // note on
+            0x83, 0x74, 0x80, 0x3C, 0x00, // note off 500 ticks later
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let tracks = import_smf(&file(0xE728, &[&track])).unwrap();
+        assert_eq!(tracks[0].config.sequences[0].length(), StepLength::Ms(500));
+
+        // frame rates other than 24, 25, 29.97 and 30, including the top byte
+        // that can't be negated, and frames without any ticks are rejected
+        for division in [0x8000, 0xE9_28, 0xE7_00, 0xFF_28] {
+            assert_eq!(
+                import_smf(&file(division, &[&track])).unwrap_err(),
+                SmfError::InvalidDivision(division)
+            );
+        }
+    }
+
+    #[test]
+    fn test_running_status_is_cancelled() {
+        // running status can't carry on after a meta or sysex event
+        for event in [&[0xFF, 0x01, 0x00][..], &[0xF0, 0x01, 0xF7]] {
+            let mut track = vec![0x00, 0x90, 0x3C, 0x64, 0x00];
+            track.extend(event);
+            track.extend([0x00, 0x3C, 0x00, 0x00, 0xFF, 0x2F, 0x00]);
+            assert_eq!(
+                import_smf(&file(480, &[&track])).unwrap_err(),
+                SmfError::InvalidStatus(0x3C)
+            );
+        }
+    }
+}
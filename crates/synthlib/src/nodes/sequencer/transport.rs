@@ -0,0 +1,99 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use firewheel::diff::{Diff, Patch};
+
+/// The order that a [super::SequencerNode] plays the steps in its loop region
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayMode {
+    /// Plays forwards and starts again from the beginning
+    #[default]
+    Loop,
+    /// Plays forwards once and then stops
+    OneShot,
+    /// Plays forwards then backwards, without repeating the first and last steps
+    PingPong,
+    /// Plays backwards and starts again from the end
+    Reverse,
+    /// Plays a random step each time
+    Random,
+    /// Moves one step forwards or backwards at random
+    RandomWalk,
+}
+
+/// Transport commands that can be sent to a [super::SequencerNode] with
+/// `NodeEventType::custom`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Starts playing from the beginning, or carries on after a pause
+    Start,
+    /// Stops playing and goes back to the beginning
+    Stop,
+    /// Stops playing but keeps the position so that playback can be resumed
+    Pause,
+    /// Goes back to the beginning without starting or stopping
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum TransportState {
+    #[default]
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// The playback state of a [super::SequencerNode], which is updated by the
+/// processor and can be read from the main thread with
+/// `FirewheelContext::node_state::<SequencerState>(node_id)`.
+#[derive(Debug, Clone, Default)]
+pub struct SequencerState {
+    shared: Arc<SharedState>,
+}
+
+#[derive(Debug, Default)]
+struct SharedState {
+    playing: AtomicBool,
+    finished: AtomicBool,
+    current_step: AtomicU32,
+    completions: AtomicU32,
+}
+
+impl SequencerState {
+    pub fn is_playing(&self) -> bool {
+        self.shared.playing.load(Ordering::Relaxed)
+    }
+
+    /// Whether a [PlayMode::OneShot] sequence has played to the end. This is
+    /// cleared when the sequence is started or reset.
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Relaxed)
+    }
+
+    /// The index of the step that is playing
+    pub fn current_step(&self) -> usize {
+        self.shared.current_step.load(Ordering::Relaxed) as usize
+    }
+
+    /// The number of times a [PlayMode::OneShot] sequence has played to the end,
+    /// which can be compared between updates to catch every completion
+    pub fn completions(&self) -> u32 {
+        self.shared.completions.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_playing(&self, playing: bool, current_step: usize) {
+        self.shared.playing.store(playing, Ordering::Relaxed);
+        self.shared
+            .current_step
+            .store(current_step as u32, Ordering::Relaxed);
+    }
+
+    pub(super) fn set_finished(&self, finished: bool) {
+        self.shared.finished.store(finished, Ordering::Relaxed);
+        if finished {
+            self.shared.completions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, NonZeroChannelCount},
+    diff::{Diff, Patch},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+};
+
+/// Limits how fast a control signal can change, smoothing out steps from a
+/// [crate::nodes::hold::HoldNode] or adding portamento to a pitch in volts per
+/// octave (see [crate::cv]).
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct SlewLimiterNode {
+    /// The time it takes to rise by `1.0`, in milliseconds. `0.0` doesn't limit
+    /// rising at all.
+    pub rise_ms: f32,
+    /// The time it takes to fall by `1.0`, in milliseconds. `0.0` doesn't limit
+    /// falling at all.
+    pub fall_ms: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlewConfig {
+    /// The number of signals to limit, each with its own input and output
+    pub channels: NonZeroChannelCount,
+}
+
+impl Default for SlewConfig {
+    fn default() -> Self {
+        Self {
+            channels: NonZeroChannelCount::MONO,
+        }
+    }
+}
+
+/// Moves towards a signal by at most a fixed step each sample
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Slew {
+    value: f32,
+}
+
+impl Slew {
+    /// The furthest a signal can move in one sample to take `ms` to move by `1.0`
+    pub fn max_step(ms: f32, sample_rate: u32) -> f32 {
+        if ms > 0.0 {
+            1000.0 / (ms * sample_rate as f32)
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    pub fn next(&mut self, target: f32, max_rise: f32, max_fall: f32) -> f32 {
+        self.value += (target - self.value).clamp(-max_fall, max_rise);
+        self.value
+    }
+}
+
+impl AudioNode for SlewLimiterNode {
+    type Configuration = SlewConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("slew_limiter")
+            .channel_config(ChannelConfig {
+                num_inputs: configuration.channels.get(),
+                num_outputs: configuration.channels.get(),
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        SlewProcessor {
+            params: *self,
+            slews: vec![Slew::default(); configuration.channels.get().get() as usize],
+            sample_rate: cx.stream_info.sample_rate.into(),
+        }
+    }
+}
+
+struct SlewProcessor {
+    params: SlewLimiterNode,
+    slews: Vec<Slew>,
+    sample_rate: u32,
+}
+
+impl AudioNodeProcessor for SlewProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<SlewLimiterNode>() {
+            self.params.apply(patch);
+        }
+
+        let frames = proc_info.frames;
+        let max_rise = Slew::max_step(self.params.rise_ms, self.sample_rate);
+        let max_fall = Slew::max_step(self.params.fall_ms, self.sample_rate);
+
+        for ((input, output), slew) in buffers
+            .inputs
+            .iter()
+            .zip(buffers.outputs.iter_mut())
+            .zip(self.slews.iter_mut())
+        {
+            for (input, output) in input[..frames].iter().zip(output[..frames].iter_mut()) {
+                *output = slew.next(*input, max_rise, max_fall);
+            }
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.sample_rate = stream_info.sample_rate.into();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rise_and_fall() {
+        // rising by 1.0 takes 10 samples and falling takes 4
+        let max_rise = Slew::max_step(10.0, 1_000);
+        let max_fall = Slew::max_step(4.0, 1_000);
+        let mut slew = Slew::default();
+
+        let rise: Vec<f32> = (0..12)
+            .map(|_| slew.next(1.0, max_rise, max_fall))
+            .collect();
+        assert!((rise[4] - 0.5).abs() < 1e-6);
+        assert!((rise[9] - 1.0).abs() < 1e-6);
+        assert_eq!(rise[11], 1.0);
+
+        let fall: Vec<f32> = (0..4).map(|_| slew.next(0.0, max_rise, max_fall)).collect();
+        assert!((fall[1] - 0.5).abs() < 1e-6);
+        assert!(fall[3].abs() < 1e-6);
+
+        // no limit jumps straight there
+        let unlimited = Slew::max_step(0.0, 1_000);
+        assert_eq!(slew.next(-3.0, unlimited, unlimited), -3.0);
+    }
+}
@@ -0,0 +1,412 @@
+//! A resonant multimode filter built around a topology-preserving transform (TPT)
+//! state-variable filter. It follows the same smoothing, declicking and silence
+//! handling approach as [super::filter::FilterNode].
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount},
+    diff::{Diff, Patch},
+    dsp::{
+        declick::{Declicker, FadeType},
+        volume::{DEFAULT_AMP_EPSILON, Volume},
+    },
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, EmptyConfig,
+        ProcBuffers, ProcInfo, ProcessStatus,
+    },
+    param::smoother::{SmoothedParam, SmoothedParamBuffer},
+};
+
+use super::upmix::{bypass, is_upmixing, stereo_inputs};
+
+pub const MIN_Q: f32 = 0.5;
+pub const MAX_Q: f32 = 25.0;
+
+/// A stereo resonant filter with lowpass, highpass, bandpass, notch, peak and
+/// allpass modes. A mono source that is only connected to the left input is
+/// played on both channels.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct SvfFilterNode {
+    /// The cutoff frequency in hertz in the range `[20.0, 20_000.0]`.
+    pub cutoff_hz: f32,
+    /// The resonance in the range `[0.5, 25.0]`, where `0.707` has no resonant peak.
+    pub q: f32,
+    pub mode: SvfMode,
+    pub slope: SvfSlope,
+    /// The overall volume.
+    pub volume: Volume,
+    /// Whether or not this node is enabled.
+    pub enabled: bool,
+}
+
+impl Default for SvfFilterNode {
+    fn default() -> Self {
+        Self {
+            cutoff_hz: 1_000.0,
+            q: FRAC_1_SQRT_2,
+            mode: SvfMode::default(),
+            slope: SvfSlope::default(),
+            volume: Volume::default(),
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SvfMode {
+    #[default]
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Peak,
+    Allpass,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SvfSlope {
+    /// A single two-pole stage
+    #[default]
+    Db12,
+    /// Two cascaded two-pole stages
+    Db24,
+}
+
+impl AudioNode for SvfFilterNode {
+    type Configuration = EmptyConfig;
+
+    fn info(&self, _config: &Self::Configuration) -> AudioNodeInfo {
+        AudioNodeInfo::new()
+            .debug_name("svf_filter")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::STEREO,
+                num_outputs: ChannelCount::STEREO,
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        _config: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        let sample_rate_recip = cx.stream_info.sample_rate_recip as f32;
+
+        let cutoff_hz = self.cutoff_hz.clamp(20.0, 20_000.0);
+        let q = self.q.clamp(MIN_Q, MAX_Q);
+        let gain = self.volume.amp_clamped(DEFAULT_AMP_EPSILON);
+
+        let mut filter = StereoSvf {
+            filters: [Svf::default(); 4],
+            coeffs: [SvfCoeffs::default(); 2],
+            mode: self.mode,
+            slope: self.slope,
+            sample_rate_recip,
+        };
+        filter.set_coeffs(cutoff_hz, q);
+
+        Processor {
+            filter,
+            cutoff_hz: SmoothedParam::new(
+                cutoff_hz,
+                Default::default(),
+                cx.stream_info.sample_rate,
+            ),
+            q: SmoothedParam::new(q, Default::default(), cx.stream_info.sample_rate),
+            gain: SmoothedParamBuffer::new(gain, Default::default(), cx.stream_info),
+            enable_declicker: Declicker::from_enabled(self.enabled),
+        }
+    }
+}
+
+struct Processor {
+    filter: StereoSvf,
+    cutoff_hz: SmoothedParam,
+    q: SmoothedParam,
+    gain: SmoothedParamBuffer,
+    enable_declicker: Declicker,
+}
+
+/// A pair of cascaded state-variable filters for each of the left and right channels
+struct StereoSvf {
+    // the left and right filters for the first stage, then for the second stage
+    filters: [Svf; 4],
+    coeffs: [SvfCoeffs; 2],
+    mode: SvfMode,
+    slope: SvfSlope,
+    sample_rate_recip: f32,
+}
+
+impl StereoSvf {
+    fn set_coeffs(&mut self, cutoff_hz: f32, q: f32) {
+        match self.slope {
+            SvfSlope::Db12 => {
+                self.coeffs[0] = SvfCoeffs::new(cutoff_hz, q, self.sample_rate_recip);
+            }
+            SvfSlope::Db24 => {
+                // only the second stage resonates, otherwise the peaks stack up
+                self.coeffs[0] = SvfCoeffs::new(cutoff_hz, FRAC_1_SQRT_2, self.sample_rate_recip);
+                self.coeffs[1] = SvfCoeffs::new(cutoff_hz, q, self.sample_rate_recip);
+            }
+        }
+    }
+
+    fn set_slope(&mut self, slope: SvfSlope) {
+        if slope != self.slope {
+            // the second stage may hold stale state from before
+            self.filters[2].reset();
+            self.filters[3].reset();
+        }
+        self.slope = slope;
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let [fl1, fr1, fl2, fr2] = &mut self.filters;
+
+        let l = fl1.process(l, &self.coeffs[0], self.mode);
+        let r = fr1.process(r, &self.coeffs[0], self.mode);
+
+        match self.slope {
+            SvfSlope::Db12 => (l, r),
+            SvfSlope::Db24 => (
+                fl2.process(l, &self.coeffs[1], self.mode),
+                fr2.process(r, &self.coeffs[1], self.mode),
+            ),
+        }
+    }
+}
+
+impl AudioNodeProcessor for Processor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        let mut coeffs_changed = false;
+
+        for patch in events.drain_patches::<SvfFilterNode>() {
+            match patch {
+                SvfFilterNodePatch::CutoffHz(cutoff) => {
+                    self.cutoff_hz.set_value(cutoff.clamp(20.0, 20_000.0));
+                }
+                SvfFilterNodePatch::Q(q) => {
+                    self.q.set_value(q.clamp(MIN_Q, MAX_Q));
+                }
+                SvfFilterNodePatch::Mode(mode) => {
+                    self.filter.mode = mode;
+                }
+                SvfFilterNodePatch::Slope(slope) => {
+                    self.filter.set_slope(slope);
+                    coeffs_changed = true;
+                }
+                SvfFilterNodePatch::Volume(volume) => {
+                    self.gain.set_value(volume.amp_clamped(DEFAULT_AMP_EPSILON));
+                }
+                SvfFilterNodePatch::Enabled(enabled) => {
+                    self.enable_declicker
+                        .fade_to_enabled(enabled, proc_info.declick_values);
+                }
+            }
+        }
+
+        let upmix = is_upmixing(2, proc_info.in_connected_mask);
+        let inputs = stereo_inputs(buffers.inputs, upmix);
+        let active_channels = if upmix { 1 } else { 2 };
+
+        if self.enable_declicker.disabled() {
+            return bypass(buffers.inputs, buffers.outputs, proc_info.frames, upmix);
+        }
+
+        let gain_is_silent = !self.gain.is_smoothing() && self.gain.target_value() < 0.00001;
+
+        if proc_info
+            .in_silence_mask
+            .all_channels_silent(active_channels)
+            || gain_is_silent
+        {
+            self.cutoff_hz.reset();
+            self.q.reset();
+            self.gain.reset();
+            self.filter.reset();
+            self.filter
+                .set_coeffs(self.cutoff_hz.target_value(), self.q.target_value());
+            self.enable_declicker.reset_to_target();
+
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let in1 = &inputs[0][..proc_info.frames];
+        let in2 = &inputs[1][..proc_info.frames];
+        let (out1, out2) = buffers.outputs.split_first_mut().unwrap();
+        let out1 = &mut out1[..proc_info.frames];
+        let out2 = &mut out2[0][..proc_info.frames];
+
+        let gain = &self.gain.get_buffer(proc_info.frames).0[..proc_info.frames];
+
+        if self.cutoff_hz.is_smoothing() || self.q.is_smoothing() {
+            for i in 0..proc_info.frames {
+                let cutoff_hz = self.cutoff_hz.next_smoothed();
+                let q = self.q.next_smoothed();
+
+                // only recalculate the coefficients every 16 frames
+                if i & (16 - 1) == 0 {
+                    self.filter.set_coeffs(cutoff_hz, q);
+                }
+
+                let (fl, fr) = self.filter.process(in1[i], in2[i]);
+
+                out1[i] = fl * gain[i];
+                out2[i] = fr * gain[i];
+            }
+
+            self.cutoff_hz.settle();
+            self.q.settle();
+
+            // the coefficients trail the smoothers by up to 16 frames, so land them
+            // on the target once smoothing has finished
+            if !self.cutoff_hz.is_smoothing() && !self.q.is_smoothing() {
+                self.filter
+                    .set_coeffs(self.cutoff_hz.target_value(), self.q.target_value());
+            }
+        } else {
+            if coeffs_changed {
+                self.filter
+                    .set_coeffs(self.cutoff_hz.target_value(), self.q.target_value());
+            }
+
+            for i in 0..proc_info.frames {
+                let (fl, fr) = self.filter.process(in1[i], in2[i]);
+
+                out1[i] = fl * gain[i];
+                out2[i] = fr * gain[i];
+            }
+        }
+
+        self.enable_declicker.process_crossfade(
+            &inputs,
+            buffers.outputs,
+            proc_info.frames,
+            proc_info.declick_values,
+            FadeType::EqualPower3dB,
+        );
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.filter.sample_rate_recip = stream_info.sample_rate_recip as f32;
+
+        self.cutoff_hz.update_sample_rate(stream_info.sample_rate);
+        self.q.update_sample_rate(stream_info.sample_rate);
+        self.gain.update_stream(stream_info);
+
+        self.filter
+            .set_coeffs(self.cutoff_hz.target_value(), self.q.target_value());
+    }
+}
+
+/// The coefficients of a TPT state-variable filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SvfCoeffs {
+    a1: f32,
+    a2: f32,
+    a3: f32,
+    k: f32,
+}
+
+impl Default for SvfCoeffs {
+    fn default() -> Self {
+        Self::new(1_000.0, FRAC_1_SQRT_2, 1.0 / 44_100.0)
+    }
+}
+
+impl SvfCoeffs {
+    pub fn new(cutoff_hz: f32, q: f32, sample_rate_recip: f32) -> Self {
+        // keep the cutoff below nyquist so the prewarping stays finite
+        let cutoff_hz = cutoff_hz.min(0.49 / sample_rate_recip);
+
+        let g = (PI * cutoff_hz * sample_rate_recip).tan();
+        let k = 1.0 / q;
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        Self { a1, a2, a3, k }
+    }
+}
+
+/// The state of a TPT state-variable filter
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Svf {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Svf {
+    pub fn reset(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+
+    #[inline]
+    pub fn process(&mut self, v0: f32, coeffs: &SvfCoeffs, mode: SvfMode) -> f32 {
+        let v3 = v0 - self.ic2eq;
+        let v1 = coeffs.a1 * self.ic1eq + coeffs.a2 * v3;
+        let v2 = self.ic2eq + coeffs.a2 * self.ic1eq + coeffs.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        let low = v2;
+        let band = v1;
+        let high = v0 - coeffs.k * v1 - v2;
+
+        match mode {
+            SvfMode::Lowpass => low,
+            SvfMode::Highpass => high,
+            SvfMode::Bandpass => band,
+            SvfMode::Notch => low + high,
+            SvfMode::Peak => low - high,
+            SvfMode::Allpass => low + high - coeffs.k * band,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs a constant input through the filter until it settles
+    fn settle(mode: SvfMode, input: f32) -> f32 {
+        let coeffs = SvfCoeffs::new(1_000.0, FRAC_1_SQRT_2, 1.0 / 48_000.0);
+        let mut svf = Svf::default();
+
+        let mut out = 0.0;
+        for _ in 0..48_000 {
+            out = svf.process(input, &coeffs, mode);
+        }
+        out
+    }
+
+    #[test]
+    fn test_dc_response() {
+        assert!((settle(SvfMode::Lowpass, 1.0) - 1.0).abs() < 1e-4);
+        assert!(settle(SvfMode::Highpass, 1.0).abs() < 1e-4);
+        assert!(settle(SvfMode::Bandpass, 1.0).abs() < 1e-4);
+        assert!((settle(SvfMode::Notch, 1.0) - 1.0).abs() < 1e-4);
+        assert!((settle(SvfMode::Allpass, 1.0) - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,81 @@
+//! Stereo nodes treat a source that is only connected to their left input as
+//! mono, and play it on both channels rather than leaving the right one silent.
+
+use firewheel::{ConnectedMask, SilenceMask, node::ProcessStatus};
+
+/// Whether a node with `channels` audio inputs is being fed a mono source on its
+/// left input only
+#[inline]
+pub fn is_upmixing(channels: usize, in_connected_mask: ConnectedMask) -> bool {
+    channels == 2 && !in_connected_mask.is_channel_connected(1)
+}
+
+/// The left and right inputs, with the left input used for both when upmixing
+#[inline]
+pub fn stereo_inputs<'a>(inputs: &[&'a [f32]], upmix: bool) -> [&'a [f32]; 2] {
+    if upmix {
+        [inputs[0], inputs[0]]
+    } else {
+        [inputs[0], inputs[1]]
+    }
+}
+
+/// Passes the inputs straight through. When upmixing the engine's bypass would
+/// leave the right channel silent, so the left input is copied to both outputs.
+pub fn bypass(
+    inputs: &[&[f32]],
+    outputs: &mut [&mut [f32]],
+    frames: usize,
+    upmix: bool,
+) -> ProcessStatus {
+    if !upmix {
+        return ProcessStatus::Bypass;
+    }
+
+    for output in outputs.iter_mut() {
+        output[..frames].copy_from_slice(&inputs[0][..frames]);
+    }
+
+    ProcessStatus::OutputsModified {
+        out_silence_mask: SilenceMask::NONE_SILENT,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_upmixing() {
+        assert!(is_upmixing(2, ConnectedMask(0b01)));
+        assert!(!is_upmixing(2, ConnectedMask(0b11)));
+        // a mono node has nothing to upmix
+        assert!(!is_upmixing(1, ConnectedMask(0b01)));
+
+        let (left, right) = ([1.0, 2.0], [3.0, 4.0]);
+        assert_eq!(stereo_inputs(&[&left, &right], false), [&left, &right]);
+        assert_eq!(stereo_inputs(&[&left, &right], true), [&left, &left]);
+    }
+
+    #[test]
+    fn test_bypass() {
+        let (left, right) = ([1.0, 2.0], [0.0, 0.0]);
+        let mut outputs = [[9.0; 2], [9.0; 2]];
+        let [out_l, out_r] = &mut outputs;
+
+        assert_eq!(
+            bypass(&[&left, &right], &mut [out_l, out_r], 2, false),
+            ProcessStatus::Bypass
+        );
+        assert_eq!(outputs, [[9.0; 2], [9.0; 2]]);
+
+        let [out_l, out_r] = &mut outputs;
+        assert_eq!(
+            bypass(&[&left, &right], &mut [out_l, out_r], 2, true),
+            ProcessStatus::OutputsModified {
+                out_silence_mask: SilenceMask::NONE_SILENT
+            }
+        );
+        assert_eq!(outputs, [left, left]);
+    }
+}
@@ -0,0 +1,229 @@
+use firewheel::{
+    SilenceMask, StreamInfo,
+    channel_config::{ChannelConfig, ChannelCount, NonZeroChannelCount},
+    diff::{Diff, Patch},
+    dsp::volume::{DEFAULT_AMP_EPSILON, Volume, db_to_amp},
+    event::NodeEventList,
+    log::RealtimeLogger,
+    node::{
+        AudioNode, AudioNodeInfo, AudioNodeProcessor, ConstructProcessorContext, ProcBuffers,
+        ProcInfo, ProcessStatus,
+    },
+    param::smoother::SmoothedParamBuffer,
+};
+
+/// The range covered by the exponential response, from a control value of `0.0`
+/// to `1.0`
+const EXPONENTIAL_RANGE_DB: f32 = 60.0;
+
+/// A voltage controlled amplifier that multiplies its audio inputs by a control
+/// input, for example from an [crate::nodes::envelope::EnvelopeNode] or
+/// [crate::nodes::lfo::LfoNode].
+///
+/// The audio inputs come first, followed by a single control input in the range
+/// `[0.0, 1.0]`. When nothing is connected to the control input the audio is only
+/// scaled by `gain`.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
+pub struct VcaNode {
+    /// The base gain applied on top of the control input
+    pub gain: Volume,
+    /// How the control input is mapped to gain
+    pub response: VcaResponse,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VcaResponse {
+    /// The control value is used directly as the gain
+    #[default]
+    Linear,
+    /// The control value is mapped to decibels, which sounds more natural for
+    /// fades and envelopes
+    Exponential,
+}
+
+impl VcaResponse {
+    #[inline]
+    pub fn gain(&self, cv: f32) -> f32 {
+        let cv = cv.clamp(0.0, 1.0);
+
+        match self {
+            Self::Linear => cv,
+            Self::Exponential => {
+                if cv <= 0.0 {
+                    0.0
+                } else {
+                    db_to_amp((cv - 1.0) * EXPONENTIAL_RANGE_DB)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VcaConfig {
+    /// The number of audio channels, not including the control input
+    pub channels: NonZeroChannelCount,
+}
+
+impl Default for VcaConfig {
+    fn default() -> Self {
+        Self {
+            channels: NonZeroChannelCount::MONO,
+        }
+    }
+}
+
+impl AudioNode for VcaNode {
+    type Configuration = VcaConfig;
+
+    fn info(&self, configuration: &Self::Configuration) -> AudioNodeInfo {
+        let channels = configuration.channels.get().get();
+
+        AudioNodeInfo::new()
+            .debug_name("vca")
+            .channel_config(ChannelConfig {
+                num_inputs: ChannelCount::new(channels + 1).unwrap(),
+                num_outputs: configuration.channels.get(),
+            })
+    }
+
+    fn construct_processor(
+        &self,
+        configuration: &Self::Configuration,
+        cx: ConstructProcessorContext,
+    ) -> impl AudioNodeProcessor {
+        VcaProcessor {
+            channels: configuration.channels.get().get() as usize,
+            response: self.response,
+            gain: SmoothedParamBuffer::new(
+                self.gain.amp_clamped(DEFAULT_AMP_EPSILON),
+                Default::default(),
+                cx.stream_info,
+            ),
+        }
+    }
+}
+
+struct VcaProcessor {
+    channels: usize,
+    response: VcaResponse,
+    gain: SmoothedParamBuffer,
+}
+
+impl AudioNodeProcessor for VcaProcessor {
+    fn process(
+        &mut self,
+        buffers: ProcBuffers,
+        proc_info: &ProcInfo,
+        events: &mut NodeEventList,
+        _logger: &mut RealtimeLogger,
+    ) -> ProcessStatus {
+        for patch in events.drain_patches::<VcaNode>() {
+            match patch {
+                VcaNodePatch::Gain(gain) => {
+                    self.gain.set_value(gain.amp_clamped(DEFAULT_AMP_EPSILON));
+                }
+                VcaNodePatch::Response(response) => self.response = response,
+            }
+        }
+
+        let cv_idx = self.channels;
+        let cv_connected = proc_info.in_connected_mask.is_channel_connected(cv_idx);
+        let cv_silent = cv_connected && proc_info.in_silence_mask.is_channel_silent(cv_idx);
+        let gain_is_silent = !self.gain.is_smoothing() && self.gain.target_value() < 0.00001;
+
+        if proc_info.in_silence_mask.all_channels_silent(self.channels)
+            || cv_silent
+            || gain_is_silent
+        {
+            self.gain.reset();
+            return ProcessStatus::ClearAllOutputs;
+        }
+
+        let (inputs, cv) = buffers.inputs.split_at(cv_idx);
+        let cv = &cv[0][..proc_info.frames];
+        let gain = &self.gain.get_buffer(proc_info.frames).0[..proc_info.frames];
+
+        let cv = cv_connected.then_some(cv);
+
+        for (input, output) in inputs.iter().zip(buffers.outputs.iter_mut()) {
+            let input = &input[..proc_info.frames];
+            let output = &mut output[..proc_info.frames];
+            apply_gain(input, output, gain, cv, self.response);
+        }
+
+        ProcessStatus::OutputsModified {
+            out_silence_mask: SilenceMask::NONE_SILENT,
+        }
+    }
+
+    fn new_stream(&mut self, stream_info: &StreamInfo) {
+        self.gain.update_stream(stream_info);
+    }
+}
+
+/// Scales a channel by the gain and, when it is connected, the control input
+#[inline]
+fn apply_gain(
+    input: &[f32],
+    output: &mut [f32],
+    gain: &[f32],
+    cv: Option<&[f32]>,
+    response: VcaResponse,
+) {
+    match cv {
+        Some(cv) => {
+            for i in 0..output.len() {
+                output[i] = input[i] * gain[i] * response.gain(cv[i]);
+            }
+        }
+        None => {
+            for i in 0..output.len() {
+                output[i] = input[i] * gain[i];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_responses() {
+        assert_eq!(VcaResponse::Linear.gain(0.0), 0.0);
+        assert_eq!(VcaResponse::Linear.gain(0.25), 0.25);
+        assert_eq!(VcaResponse::Linear.gain(1.0), 1.0);
+
+        assert_eq!(VcaResponse::Exponential.gain(0.0), 0.0);
+        assert!((VcaResponse::Exponential.gain(1.0) - 1.0).abs() < 1e-6);
+        // halfway is 30 dB down
+        assert!((VcaResponse::Exponential.gain(0.5) - db_to_amp(-30.0)).abs() < 1e-6);
+
+        // control values outside of [0, 1] are clamped
+        for response in [VcaResponse::Linear, VcaResponse::Exponential] {
+            assert_eq!(response.gain(-0.5), 0.0);
+            assert_eq!(response.gain(2.0), response.gain(1.0));
+        }
+    }
+
+    #[test]
+    fn test_control_input() {
+        let input = [1.0, -1.0, 0.5, 1.0];
+        let gain = [0.5; 4];
+        let mut output = [0.0; 4];
+
+        apply_gain(
+            &input,
+            &mut output,
+            &gain,
+            Some(&[1.0, 0.5, 0.0, 3.0]),
+            VcaResponse::Linear,
+        );
+        assert_eq!(output, [0.5, -0.25, 0.0, 0.5]);
+
+        // without a control input only the gain is applied
+        apply_gain(&input, &mut output, &gain, None, VcaResponse::Linear);
+        assert_eq!(output, [0.5, -0.5, 0.25, 0.5]);
+    }
+}
@@ -10,6 +10,9 @@ pub mod processor;
 use processor::WaveTableProcessor;
 
 /// A node that produces different [WaveType]s from a wavetable.
+///
+/// The input is the pitch to play in volts per octave (see [crate::cv]), for
+/// example from a [crate::nodes::sequencer::SequencerNode].
 #[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Default)]
 pub struct WaveTableNode;
 
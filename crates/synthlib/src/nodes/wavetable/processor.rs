@@ -3,15 +3,14 @@ use firewheel::{
     event::NodeEventList,
     node::{AudioNodeProcessor, ProcBuffers, ProcInfo, ProcessStatus},
 };
-use wavetable::{WaveTableGenerator, WaveTableSampler, WaveType};
+use wavetable::{WaveTableSampler, WaveTables};
+
+use crate::cv::voltage_to_frequency;
 
 /// A processer with `N` samplers
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct WaveTableProcessor<const N: usize> {
-    sine_wave: [f32; 64],
-    triangle_wave: [f32; 64],
-    saw_wave: [f32; 64],
-    square_wave: [f32; 64],
+    tables: WaveTables<64>,
     base_frequency: f32,
     samplers: [WaveTableSampler; N],
     enabled: bool,
@@ -21,10 +20,7 @@ impl<const N: usize> WaveTableProcessor<N> {
     pub fn new(enabled: bool, base_frequency: f32, samplers: [WaveTableSampler; N]) -> Self {
         Self {
             enabled,
-            sine_wave: WaveTableGenerator::sin::<64>(),
-            triangle_wave: WaveTableGenerator::triangle::<64>(),
-            saw_wave: WaveTableGenerator::saw::<64>(),
-            square_wave: WaveTableGenerator::square::<64>(),
+            tables: WaveTables::default(),
             base_frequency,
             samplers,
         }
@@ -40,15 +36,11 @@ impl<const N: usize> AudioNodeProcessor for WaveTableProcessor<N> {
         _logger: &mut firewheel::log::RealtimeLogger,
     ) -> ProcessStatus {
         for (idx, s) in buffers.outputs[0].iter_mut().enumerate() {
+            let frequency = voltage_to_frequency(buffers.inputs[0][idx]);
             let mut val = 0.0;
             for sampler in self.samplers.iter_mut() {
-                let wave_table = match sampler.wave_type {
-                    WaveType::Sine => &self.sine_wave,
-                    WaveType::Square => &self.square_wave,
-                    WaveType::Triangle => &self.triangle_wave,
-                    WaveType::Saw => &self.saw_wave,
-                };
-                val += sampler.sample(buffers.inputs[0][idx], wave_table);
+                let wave_table = self.tables.get(sampler.wave_type);
+                val += sampler.sample(frequency, wave_table);
             }
 
             *s = val / N as f32;
@@ -0,0 +1,185 @@
+use std::{fmt, str::FromStr};
+
+use firewheel::diff::{Diff, Patch, RealtimeClone};
+
+use crate::tuning::Tuning;
+
+/// The standard concert pitch of A4
+pub const DEFAULT_A4_HZ: f32 = 440.0;
+
+/// The MIDI note number of A4
+const A4_MIDI: f32 = 69.0;
+
+/// A pitch given either as a frequency or as a MIDI note number. MIDI notes are
+/// only converted to a frequency when played, so the same notes can be used with
+/// a different reference pitch.
+#[derive(Diff, Patch, RealtimeClone, Debug, Clone, Copy, PartialEq)]
+pub enum Pitch {
+    Hz(f32),
+    /// A MIDI note number, which may be fractional after transposing
+    Midi(f32),
+}
+
+impl Pitch {
+    /// The frequency of the pitch, using `a4_hz` as the reference for MIDI notes
+    pub fn hz(&self, a4_hz: f32) -> f32 {
+        match self {
+            Self::Hz(hz) => *hz,
+            Self::Midi(note) => midi_to_hz(*note, a4_hz),
+        }
+    }
+
+    /// The frequency of the pitch with MIDI notes played in a [Tuning], or `None`
+    /// if the tuning doesn't map the note
+    pub fn tuned_hz(&self, tuning: &Tuning) -> Option<f32> {
+        match self {
+            Self::Hz(hz) => Some(*hz),
+            Self::Midi(note) => tuning.frequency(*note),
+        }
+    }
+
+    /// Moves the pitch up or down by a number of semitones
+    pub fn transposed(self, semitones: f32) -> Self {
+        match self {
+            Self::Hz(hz) => Self::Hz(hz * (semitones / 12.0).exp2()),
+            Self::Midi(note) => Self::Midi(note + semitones),
+        }
+    }
+}
+
+impl From<u8> for Pitch {
+    fn from(note: u8) -> Self {
+        Self::Midi(note as f32)
+    }
+}
+
+impl FromStr for Pitch {
+    type Err = NoteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_note_name(s).map(Pitch::from)
+    }
+}
+
+/// Converts a MIDI note number to a frequency, where note 69 is `a4_hz`
+#[inline]
+pub fn midi_to_hz(note: f32, a4_hz: f32) -> f32 {
+    a4_hz * ((note - A4_MIDI) / 12.0).exp2()
+}
+
+/// Converts a frequency to a (possibly fractional) MIDI note number
+#[inline]
+pub fn hz_to_midi(hz: f32, a4_hz: f32) -> f32 {
+    A4_MIDI + 12.0 * (hz / a4_hz).log2()
+}
+
+/// Parses a note name in scientific pitch notation such as `"A4"`, `"C#3"` or
+/// `"Eb5"` into a MIDI note number. Middle C is `"C4"` (note 60), and octave `-1`
+/// is the lowest octave.
+pub fn parse_note_name(name: &str) -> Result<u8, NoteParseError> {
+    let mut chars = name.trim().chars().peekable();
+
+    let letter = chars.next().ok_or(NoteParseError::Empty)?;
+    let mut semitone: i32 = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Err(NoteParseError::InvalidLetter(letter)),
+    };
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '#' | '♯' => semitone += 1,
+            'b' | '♭' => semitone -= 1,
+            _ => break,
+        }
+        chars.next();
+    }
+
+    let octave_text: String = chars.collect();
+    let octave: i32 = octave_text
+        .parse()
+        .map_err(|_| NoteParseError::InvalidOctave(octave_text.clone()))?;
+
+    // an octave too far out to work the note out from can't be in range either
+    let note = octave
+        .checked_add(1)
+        .and_then(|octave| octave.checked_mul(12))
+        .and_then(|note| note.checked_add(semitone))
+        .ok_or(NoteParseError::InvalidOctave(octave_text))?;
+    u8::try_from(note)
+        .ok()
+        .filter(|note| *note <= 127)
+        .ok_or(NoteParseError::OutOfRange(note))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteParseError {
+    Empty,
+    InvalidLetter(char),
+    InvalidOctave(String),
+    /// The note is outside of the MIDI range of 0 to 127
+    OutOfRange(i32),
+}
+
+impl fmt::Display for NoteParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty note name"),
+            Self::InvalidLetter(c) => write!(f, "invalid note letter '{c}'"),
+            Self::InvalidOctave(octave) => write!(f, "invalid octave '{octave}'"),
+            Self::OutOfRange(note) => write!(f, "note {note} is outside the MIDI range"),
+        }
+    }
+}
+
+impl std::error::Error for NoteParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_note_name() {
+        assert_eq!(parse_note_name("A4"), Ok(69));
+        assert_eq!(parse_note_name("C4"), Ok(60));
+        assert_eq!(parse_note_name("C#3"), Ok(49));
+        assert_eq!(parse_note_name("Eb5"), Ok(75));
+        assert_eq!(parse_note_name("c-1"), Ok(0));
+        assert_eq!(parse_note_name("G9"), Ok(127));
+        assert_eq!(parse_note_name("B#3"), Ok(60));
+
+        assert_eq!(parse_note_name(""), Err(NoteParseError::Empty));
+        assert_eq!(
+            parse_note_name("H4"),
+            Err(NoteParseError::InvalidLetter('H'))
+        );
+        assert_eq!(
+            parse_note_name("A"),
+            Err(NoteParseError::InvalidOctave(String::new()))
+        );
+        assert_eq!(parse_note_name("G#9"), Err(NoteParseError::OutOfRange(128)));
+        assert_eq!(parse_note_name("C10"), Err(NoteParseError::OutOfRange(132)));
+        assert_eq!(
+            parse_note_name("c2147483647"),
+            Err(NoteParseError::InvalidOctave("2147483647".into()))
+        );
+        assert_eq!(
+            parse_note_name("C-2147483648"),
+            Err(NoteParseError::InvalidOctave("-2147483648".into()))
+        );
+    }
+
+    #[test]
+    fn test_pitch_hz() {
+        assert_eq!(Pitch::Midi(69.0).hz(DEFAULT_A4_HZ), 440.0);
+        assert_eq!(Pitch::Midi(57.0).hz(432.0), 216.0);
+        assert_eq!(Pitch::Midi(60.0).transposed(-12.0), Pitch::Midi(48.0));
+        assert_eq!(Pitch::Hz(220.0).transposed(12.0), Pitch::Hz(440.0));
+        assert!((hz_to_midi(261.6256, DEFAULT_A4_HZ) - 60.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,107 @@
+use firewheel::diff::{Diff, Patch};
+
+/// The pattern of intervals that make up a scale
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleKind {
+    #[default]
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Chromatic,
+}
+
+impl ScaleKind {
+    /// The semitones of each note above the root, within one octave
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Self::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Self::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            Self::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Self::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Self::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Self::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Self::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Self::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Self::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Self::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Self::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+/// A key, made of a root note and the kind of scale built on it
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    /// The MIDI note number of the root, where 60 is middle C
+    pub root: u8,
+    pub kind: ScaleKind,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::new(60, ScaleKind::default())
+    }
+}
+
+impl Scale {
+    pub const fn new(root: u8, kind: ScaleKind) -> Self {
+        Self { root, kind }
+    }
+
+    /// The number of notes in each octave of the scale
+    pub fn len(&self) -> usize {
+        self.kind.intervals().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The MIDI note of a degree of the scale, where 0 is the root. Degrees past
+    /// the end of the scale carry on into the octaves above and below, and notes
+    /// outside of the MIDI range are clamped to it.
+    pub fn note(&self, degree: i32) -> u8 {
+        let intervals = self.kind.intervals();
+        let len = intervals.len() as i32;
+
+        let octave = degree.div_euclid(len);
+        let interval = intervals[degree.rem_euclid(len) as usize] as i64;
+
+        // far off degrees would overflow an i32 before they are clamped
+        (self.root as i64 + octave as i64 * 12 + interval).clamp(0, 127) as u8
+    }
+
+    /// Whether a MIDI note is in the scale, in any octave
+    pub fn contains(&self, note: u8) -> bool {
+        let semitone = (note as i32 - self.root as i32).rem_euclid(12) as u8;
+        self.kind.intervals().contains(&semitone)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scale_degrees() {
+        let c_major = Scale::new(60, ScaleKind::Major);
+        assert_eq!(c_major.note(0), 60);
+        assert_eq!(c_major.note(4), 67);
+        assert_eq!(c_major.note(7), 72);
+        assert_eq!(c_major.note(-1), 59);
+        assert_eq!(c_major.note(100), 127);
+
+        let a_minor_pentatonic = Scale::new(57, ScaleKind::MinorPentatonic);
+        assert_eq!(a_minor_pentatonic.note(6), 72);
+        assert!(a_minor_pentatonic.contains(64));
+        assert!(a_minor_pentatonic.contains(33));
+        assert!(!a_minor_pentatonic.contains(59));
+    }
+}
@@ -0,0 +1,100 @@
+use firewheel::diff::{Diff, Patch};
+
+/// A musical length expressed as a fraction of a whole note, so that a quarter
+/// note (`1/4`) is one beat. Lengths can be dotted or played as triplets.
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq)]
+pub struct NoteValue {
+    pub numerator: u32,
+    pub denominator: u32,
+    pub modifier: NoteModifier,
+}
+
+#[derive(Diff, Patch, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoteModifier {
+    #[default]
+    Straight,
+    /// One and a half times the length
+    Dotted,
+    /// Two thirds of the length, so three fit in the space of two
+    Triplet,
+}
+
+impl Default for NoteValue {
+    fn default() -> Self {
+        Self::QUARTER
+    }
+}
+
+impl NoteValue {
+    pub const WHOLE: Self = Self::new(1, 1);
+    pub const HALF: Self = Self::new(1, 2);
+    pub const QUARTER: Self = Self::new(1, 4);
+    pub const EIGHTH: Self = Self::new(1, 8);
+    pub const SIXTEENTH: Self = Self::new(1, 16);
+    pub const THIRTY_SECOND: Self = Self::new(1, 32);
+
+    pub const fn new(numerator: u32, denominator: u32) -> Self {
+        Self {
+            numerator,
+            denominator,
+            modifier: NoteModifier::Straight,
+        }
+    }
+
+    pub const fn dotted(self) -> Self {
+        Self {
+            modifier: NoteModifier::Dotted,
+            ..self
+        }
+    }
+
+    pub const fn triplet(self) -> Self {
+        Self {
+            modifier: NoteModifier::Triplet,
+            ..self
+        }
+    }
+
+    /// The length in beats, where a beat is a quarter note
+    pub fn beats(&self) -> f64 {
+        if self.denominator == 0 {
+            return 0.0;
+        }
+
+        let beats = 4.0 * self.numerator as f64 / self.denominator as f64;
+        match self.modifier {
+            NoteModifier::Straight => beats,
+            NoteModifier::Dotted => beats * 1.5,
+            NoteModifier::Triplet => beats * 2.0 / 3.0,
+        }
+    }
+
+    /// The length in seconds at the given tempo
+    pub fn seconds(&self, bpm: f32) -> f64 {
+        beats_to_seconds(self.beats(), bpm)
+    }
+}
+
+/// Converts a number of beats to seconds at the given tempo
+pub fn beats_to_seconds(beats: f64, bpm: f32) -> f64 {
+    if bpm <= 0.0 {
+        return 0.0;
+    }
+
+    beats * 60.0 / bpm as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_note_value_seconds() {
+        assert_eq!(NoteValue::QUARTER.seconds(120.0), 0.5);
+        assert_eq!(NoteValue::WHOLE.seconds(120.0), 2.0);
+        assert_eq!(NoteValue::EIGHTH.dotted().seconds(120.0), 0.375);
+        assert_eq!(NoteValue::QUARTER.triplet().seconds(90.0), 4.0 / 9.0);
+        assert_eq!(NoteValue::new(3, 16).beats(), 0.75);
+        assert_eq!(NoteValue::QUARTER.seconds(0.0), 0.0);
+    }
+}
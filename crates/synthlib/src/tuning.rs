@@ -0,0 +1,433 @@
+use std::fmt;
+
+use crate::pitch::midi_to_hz;
+
+/// The number of MIDI notes a [Tuning] gives frequencies for
+const NUM_NOTES: usize = 128;
+
+/// A frequency for every MIDI note, for playing in tunings other than 12 tone
+/// equal temperament. Tunings are usually loaded from a Scala scale file, with an
+/// optional keyboard mapping to choose which notes of the scale the MIDI notes play.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    /// The frequency of each note, or `0.0` for notes that aren't mapped
+    frequencies: [f32; NUM_NOTES],
+}
+
+impl Tuning {
+    /// The usual 12 tone equal temperament, where note 69 is `a4_hz`
+    pub fn equal_temperament(a4_hz: f32) -> Self {
+        Self {
+            frequencies: std::array::from_fn(|note| midi_to_hz(note as f32, a4_hz)),
+        }
+    }
+
+    /// Maps a scale onto the MIDI notes
+    pub fn new(scale: &ScalaScale, mapping: &KeyboardMapping) -> Result<Self, TuningError> {
+        let reference = mapping
+            .degree(mapping.reference_note as i32)
+            .ok_or(TuningError::new(0, TuningErrorKind::UnmappedReference))?;
+        let reference_cents = scale.cents(reference);
+
+        let frequencies = std::array::from_fn(|note| {
+            let in_range = (mapping.first_note..=mapping.last_note).contains(&(note as u8));
+            match mapping.degree(note as i32).filter(|_| in_range) {
+                Some(degree) => {
+                    let cents = scale.cents(degree) - reference_cents;
+                    (mapping.reference_hz * (cents / 1200.0).exp2()) as f32
+                }
+                None => 0.0,
+            }
+        });
+
+        Ok(Self { frequencies })
+    }
+
+    /// Loads a tuning from the text of a Scala `.scl` file, and optionally a `.kbm`
+    /// keyboard mapping. Without a mapping the scale starts on middle C at its
+    /// usual frequency.
+    pub fn from_scala(scl: &str, kbm: Option<&str>) -> Result<Self, TuningError> {
+        let scale = ScalaScale::parse(scl)?;
+        let mapping = match kbm {
+            Some(kbm) => KeyboardMapping::parse(kbm)?,
+            None => KeyboardMapping::default(),
+        };
+        Self::new(&scale, &mapping)
+    }
+
+    /// The frequency of a MIDI note, or `None` if the note isn't mapped. Notes
+    /// between two MIDI notes, for example after transposing by a fraction of a
+    /// semitone, are spaced evenly in pitch between them.
+    pub fn frequency(&self, note: f32) -> Option<f32> {
+        if !(0.0..=(NUM_NOTES - 1) as f32).contains(&note) {
+            return None;
+        }
+
+        let low = note.floor() as usize;
+        let high = note.ceil() as usize;
+        let (low_hz, high_hz) = (self.frequencies[low], self.frequencies[high]);
+        if low_hz <= 0.0 || high_hz <= 0.0 {
+            return None;
+        }
+
+        Some(if low == high {
+            low_hz
+        } else {
+            low_hz * (high_hz / low_hz).powf(note - low as f32)
+        })
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::equal_temperament(crate::pitch::DEFAULT_A4_HZ)
+    }
+}
+
+/// A scale from a Scala `.scl` file, made of the pitches of each degree above the
+/// root. The last degree is the interval the scale repeats at, which is usually
+/// an octave.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalaScale {
+    pub description: String,
+    /// The pitch of each degree above the root in cents, not including the root
+    pub cents: Vec<f64>,
+}
+
+impl ScalaScale {
+    pub fn parse(text: &str) -> Result<Self, TuningError> {
+        let mut lines = lines(text);
+
+        let (_, description) = lines.next().ok_or(TuningError::new(
+            0,
+            TuningErrorKind::MissingLine("description"),
+        ))?;
+        let (line, count) = lines.next().ok_or(TuningError::new(
+            0,
+            TuningErrorKind::MissingLine("number of notes"),
+        ))?;
+        let count: usize = parse_number(line, count)?;
+        if count == 0 {
+            return Err(TuningError::new(line, TuningErrorKind::EmptyScale));
+        }
+
+        let cents = lines
+            .by_ref()
+            .take(count)
+            .map(|(line, pitch)| parse_pitch(line, pitch))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if cents.len() < count {
+            return Err(TuningError::new(
+                line,
+                TuningErrorKind::WrongNoteCount {
+                    expected: count,
+                    found: cents.len(),
+                },
+            ));
+        }
+
+        Ok(Self {
+            description: description.to_string(),
+            cents,
+        })
+    }
+
+    /// The pitch of a degree in cents above the root, carrying on into the
+    /// repeats of the scale above and below
+    pub fn cents(&self, degree: i32) -> f64 {
+        let len = self.cents.len() as i32;
+        let period = self.cents[self.cents.len() - 1];
+
+        let repeat = degree.div_euclid(len);
+        let cents = match degree.rem_euclid(len) {
+            0 => 0.0,
+            i => self.cents[i as usize - 1],
+        };
+
+        repeat as f64 * period + cents
+    }
+}
+
+/// A Scala `.kbm` keyboard mapping, which chooses the degree of the scale that
+/// each MIDI note plays and the frequency the scale is tuned to
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    pub first_note: u8,
+    pub last_note: u8,
+    /// The note that plays the root of the scale
+    pub middle_note: u8,
+    /// The note that is tuned to `reference_hz`
+    pub reference_note: u8,
+    pub reference_hz: f64,
+    /// The degree of the scale that each repeat of the mapping moves by. `0` uses
+    /// the number of notes in the mapping.
+    pub octave_degree: u32,
+    /// The degree played by each note of one repeat of the mapping, starting from
+    /// the middle note, or `None` for notes that aren't played. An empty mapping
+    /// plays the degrees of the scale in order.
+    pub map: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        Self {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 60,
+            reference_hz: midi_to_hz(60.0, crate::pitch::DEFAULT_A4_HZ) as f64,
+            octave_degree: 0,
+            map: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub fn parse(text: &str) -> Result<Self, TuningError> {
+        let mut lines = lines(text);
+        let mut next = |name| {
+            lines
+                .next()
+                .ok_or(TuningError::new(0, TuningErrorKind::MissingLine(name)))
+        };
+
+        let (line, size) = next("map size")?;
+        let size: usize = parse_number(line, size)?;
+        let (line, first_note) = next("first note")?;
+        let first_note = parse_number(line, first_note)?;
+        let (line, last_note) = next("last note")?;
+        let last_note = parse_number(line, last_note)?;
+        let (line, middle_note) = next("middle note")?;
+        let middle_note = parse_number(line, middle_note)?;
+        let (line, reference_note) = next("reference note")?;
+        let reference_note = parse_number(line, reference_note)?;
+        let (line, reference_hz) = next("reference frequency")?;
+        let reference_hz = parse_number(line, reference_hz)?;
+        let (line, octave_degree) = next("octave degree")?;
+        let octave_degree = parse_number(line, octave_degree)?;
+
+        // a mapping can leave off the notes at the end, which aren't played
+        let mut map = vec![None; size];
+        for (i, (line, degree)) in lines.take(size).enumerate() {
+            if degree != "x" {
+                map[i] = Some(parse_number(line, degree)?);
+            }
+        }
+
+        Ok(Self {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_hz,
+            octave_degree,
+            map,
+        })
+    }
+
+    /// The degree of the scale a MIDI note plays, or `None` if it isn't played
+    fn degree(&self, note: i32) -> Option<i32> {
+        let offset = note - self.middle_note as i32;
+        if self.map.is_empty() {
+            return Some(offset);
+        }
+
+        let size = self.map.len() as i32;
+        let octave_degree = match self.octave_degree {
+            0 => size,
+            degree => degree as i32,
+        };
+
+        let degree = self.map[offset.rem_euclid(size) as usize]?;
+        Some(degree + offset.div_euclid(size) * octave_degree)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuningErrorKind {
+    /// The file ends before a line it needs, with the name of the line
+    MissingLine(&'static str),
+    InvalidNumber(String),
+    /// A pitch that isn't a number of cents or a positive ratio
+    InvalidPitch(String),
+    /// A scale with fewer notes than it says it has
+    WrongNoteCount {
+        expected: usize,
+        found: usize,
+    },
+    EmptyScale,
+    /// The reference note of a keyboard mapping doesn't play a note of the scale
+    UnmappedReference,
+}
+
+/// An error in a Scala file, with the line it was found on
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningError {
+    /// The line of the error counting from 1, or `0` when the file ends early
+    pub line: usize,
+    pub kind: TuningErrorKind,
+}
+
+impl TuningError {
+    fn new(line: usize, kind: TuningErrorKind) -> Self {
+        Self { line, kind }
+    }
+}
+
+impl fmt::Display for TuningErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingLine(name) => write!(f, "missing the {name}"),
+            Self::InvalidNumber(text) => write!(f, "invalid number '{text}'"),
+            Self::InvalidPitch(text) => write!(f, "invalid pitch '{text}'"),
+            Self::WrongNoteCount { expected, found } => {
+                write!(f, "expected {expected} notes but found {found}")
+            }
+            Self::EmptyScale => write!(f, "the scale has no notes"),
+            Self::UnmappedReference => write!(f, "the reference note isn't mapped"),
+        }
+    }
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.kind),
+            line => write!(f, "line {line}: {}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for TuningError {}
+
+/// The lines of a Scala file with their line numbers, skipping comments. The
+/// description of a scale can be blank, so blank lines are kept.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('!'))
+        .map(|(i, line)| (i + 1, line.trim()))
+}
+
+/// Parses the first word of a line, as anything after it is a comment
+fn parse_number<T: std::str::FromStr>(line: usize, text: &str) -> Result<T, TuningError> {
+    let word = text.split_whitespace().next().unwrap_or_default();
+    word.parse()
+        .map_err(|_| TuningError::new(line, TuningErrorKind::InvalidNumber(word.to_string())))
+}
+
+/// Parses a pitch in cents, which always has a `.`, or as a ratio like `3/2`
+fn parse_pitch(line: usize, text: &str) -> Result<f64, TuningError> {
+    let word = text.split_whitespace().next().unwrap_or_default();
+    let error = || TuningError::new(line, TuningErrorKind::InvalidPitch(word.to_string()));
+
+    if word.contains('.') {
+        return word.parse().map_err(|_| error());
+    }
+
+    let (numerator, denominator) = word.split_once('/').unwrap_or((word, "1"));
+    let numerator: f64 = numerator.parse().map_err(|_| error())?;
+    let denominator: f64 = denominator.parse().map_err(|_| error())?;
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(error());
+    }
+
+    Ok(1200.0 * (numerator / denominator).log2())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const JUST_MAJOR: &str = "! just.scl
+!
+Just major scale
+ 7
+!
+ 9/8
+ 5/4
+ 4/3
+ 3/2
+ 5/3
+ 15/8
+ 2/1
+";
+
+    #[test]
+    fn test_scala_scale() {
+        let scale = ScalaScale::parse(JUST_MAJOR).unwrap();
+        assert_eq!(scale.description, "Just major scale");
+        assert_eq!(scale.cents.len(), 7);
+        assert!((scale.cents(4) - 701.955).abs() < 1e-3);
+        assert!((scale.cents(7) - 1200.0).abs() < 1e-9);
+        assert!((scale.cents(-3) - (-1200.0 + 701.955)).abs() < 1e-3);
+
+        let tuning = Tuning::from_scala(JUST_MAJOR, None).unwrap();
+        let c4 = tuning.frequency(60.0).unwrap();
+        assert!((c4 - 261.6256).abs() < 1e-3);
+        assert!((tuning.frequency(64.0).unwrap() / c4 - 1.5).abs() < 1e-6);
+        assert!((tuning.frequency(67.0).unwrap() / c4 - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_keyboard_mapping() {
+        // the white keys play the scale, with A4 tuned to 440 Hz
+        let kbm = "! white keys
+12
+0
+127
+60
+69
+440.0
+7
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+        let tuning = Tuning::from_scala(JUST_MAJOR, Some(kbm)).unwrap();
+        assert_eq!(tuning.frequency(69.0), Some(440.0));
+        assert_eq!(tuning.frequency(61.0), None);
+        assert!(
+            (tuning.frequency(72.0).unwrap() / tuning.frequency(60.0).unwrap() - 2.0).abs() < 1e-6
+        );
+        assert!(
+            (tuning.frequency(67.0).unwrap() / tuning.frequency(60.0).unwrap() - 1.5).abs() < 1e-6
+        );
+
+        let equal = Tuning::equal_temperament(440.0);
+        assert_eq!(equal.frequency(69.0), Some(440.0));
+        assert!((equal.frequency(69.5).unwrap() - midi_to_hz(69.5, 440.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_scala_errors() {
+        assert_eq!(
+            ScalaScale::parse("scale\n2\n100.0\nthree\n").unwrap_err(),
+            TuningError::new(4, TuningErrorKind::InvalidPitch("three".to_string()))
+        );
+        assert_eq!(
+            ScalaScale::parse("scale\n3\n100.0\n").unwrap_err(),
+            TuningError::new(
+                2,
+                TuningErrorKind::WrongNoteCount {
+                    expected: 3,
+                    found: 1
+                }
+            )
+        );
+        assert_eq!(
+            KeyboardMapping::parse("12\n0\n").unwrap_err().kind,
+            TuningErrorKind::MissingLine("last note")
+        );
+    }
+}
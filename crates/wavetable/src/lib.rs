@@ -25,9 +25,9 @@ impl WaveTableGenerator {
             samples[x] = gradient * x as f32 - 1.0;
         }
 
-        // now mirror to the end
+        // then fall back from 1 towards -1 by the end
         for x in halfway_idx..R {
-            samples[x] = samples[x - halfway_idx];
+            samples[x] = 1.0 - gradient * (x - halfway_idx) as f32;
         }
 
         samples
@@ -64,6 +64,38 @@ pub enum WaveType {
     Saw,
 }
 
+/// A set of wave tables, one for each [WaveType], each with `R` samples
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WaveTables<const R: usize> {
+    sine: [f32; R],
+    triangle: [f32; R],
+    saw: [f32; R],
+    square: [f32; R],
+}
+
+impl<const R: usize> Default for WaveTables<R> {
+    fn default() -> Self {
+        Self {
+            sine: WaveTableGenerator::sin::<R>(),
+            triangle: WaveTableGenerator::triangle::<R>(),
+            saw: WaveTableGenerator::saw::<R>(),
+            square: WaveTableGenerator::square::<R>(),
+        }
+    }
+}
+
+impl<const R: usize> WaveTables<R> {
+    /// Gets the table for the given [WaveType]
+    pub fn get(&self, wave_type: WaveType) -> &[f32; R] {
+        match wave_type {
+            WaveType::Sine => &self.sine,
+            WaveType::Square => &self.square,
+            WaveType::Triangle => &self.triangle,
+            WaveType::Saw => &self.saw,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 #[cfg_attr(feature = "firewheel", derive(Diff, Patch))]
 /// Samples a wave table at a given frequency and sample rate